@@ -9,7 +9,22 @@
 //!
 //! It could be useful for people who want to understand how a piece buffer might be implemented
 //! in Rust.
+use std::borrow::Cow;
 use std::cmp::{max, min};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::VecDeque;
+use std::convert::Infallible;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::io::{self, Read};
+use std::ops::{Bound, Range, RangeBounds};
+use std::panic::{self, AssertUnwindSafe};
+use std::str::FromStr;
+use std::sync::{Arc, OnceLock};
+
+/// The number of edits a table remembers for undo unless it is created with
+/// with_history_limit.
+pub const DEFAULT_HISTORY_LIMIT: usize = 100;
 
 /// A section of the buffer representing some text. Equivalent to a slice of a string.
 #[derive(Copy, Clone, Debug)]
@@ -59,10 +74,44 @@ impl Piece {
     }
 }
 
-type Buffer = String;
+/// Buffers are shared between tables made from one another, such as snapshots.
+/// A buffer is only ever extended while it isn't shared, so sharing never copies text.
+/// Buffers hold bytes so that tables can hold binary data, but while a table isn't
+/// binary every piece holds valid UTF-8, as only text is ever added to it.
+type Buffer = Arc<Vec<u8>>;
+#[derive(Clone)]
 pub struct PieceTable {
     buffers: Vec<Buffer>,
     pieces: Vec<Piece>,
+    max_pieces: Option<usize>,
+    readonly_regions: Vec<Range<usize>>,
+    line_count: OnceLock<usize>,
+    len: OnceLock<usize>,
+    piece_ends: OnceLock<Vec<usize>>,
+    normalize_eol: bool,
+    binary: bool,
+    original_len: Option<usize>,
+    version: u64,
+    undo_stack: VecDeque<HistoryEntry>,
+    redo_stack: Vec<HistoryEntry>,
+    history_limit: usize,
+    anchors: Vec<Option<Anchor>>,
+    buffer_growth: BufferGrowth,
+    transaction_depth: usize,
+    transaction_start: Option<HistoryEntry>,
+    transaction_edited: bool,
+    pending_change: Option<Change>,
+    observers: Observers,
+    #[cfg(test)]
+    splits: usize,
+}
+
+/// The state of a document before an edit, kept so that the edit can be undone.
+/// Buffers are append-only, so the pieces and read-only regions are all that change.
+#[derive(Clone)]
+struct HistoryEntry {
+    pieces: Vec<Piece>,
+    readonly_regions: Vec<Range<usize>>,
 }
 
 /// Represents the point in the piece table, specified as the index of a piece
@@ -82,52 +131,702 @@ impl Location {
     }
 }
 
+/// Identifies an anchor added to a piece table with add_anchor.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct AnchorId(usize);
+
+/// Which side of text inserted exactly at an anchor the anchor ends up on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Bias {
+    /// The anchor stays before the inserted text.
+    Left,
+    /// The anchor moves to after the inserted text.
+    Right,
+}
+
+/// How big a piece table makes each new add buffer.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BufferGrowth {
+    /// Each new buffer holds as much as all the existing buffers put together, so that
+    /// a long editing session needs few buffers. This is the default.
+    #[default]
+    Proportional,
+    /// Each new buffer holds the given number of bytes, or more if a single insert
+    /// needs it, which keeps memory use down for large documents.
+    Fixed(usize),
+}
+
+/// A byte position in the document that moves with edits around it.
+#[derive(Clone, Copy, Debug)]
+struct Anchor {
+    position: usize,
+    bias: Bias,
+}
+
+/// A single change to a document: remove bytes starting at start, then insert text there.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Hunk {
+    pub start: usize,
+    pub remove: usize,
+    pub insert: String,
+}
+
+/// A change made to the text of a document, given as the byte range of the text removed,
+/// in the document as it was before, and the byte range of the text that replaced it, in
+/// the document as it is after. Either range may be empty.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Change {
+    pub removed: Range<usize>,
+    pub inserted: Range<usize>,
+}
+
+impl Change {
+    /// Combines this change with the next change made after it into a single change
+    /// covering both, for edits reported together such as those in a transaction.
+    fn followed_by(self, next: &Change) -> Change {
+        let start = min(self.inserted.start, next.removed.start);
+        let end = max(self.inserted.end, next.removed.end);
+        Change {
+            removed: start..end + self.removed.len() - self.inserted.len(),
+            inserted: start..end + next.inserted.len() - next.removed.len(),
+        }
+    }
+}
+
+/// A function registered with on_change.
+type Observer = Box<dyn FnMut(&PieceTable, &Change) + Send + Sync>;
+
+/// The functions registered with on_change. They belong to one table, so a clone of the
+/// table starts without any.
+#[derive(Default)]
+struct Observers(Vec<Observer>);
+
+impl Clone for Observers {
+    fn clone(&self) -> Self {
+        Observers::default()
+    }
+}
+
+/// A description of one piece of a piece table, returned by pieces_debug.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PieceInfo<'a> {
+    /// The index of the buffer the piece references.
+    pub buffer_index: usize,
+    /// The byte range of the buffer the piece references.
+    pub buffer_range: Range<usize>,
+    /// The byte offset in the document at which the piece starts.
+    pub start: usize,
+    /// The text of the piece.
+    pub text: &'a str,
+}
+
+/// The reasons an edit can be rejected. Each holds the byte position that caused it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EditError {
+    /// The position is past the end of the document.
+    OutOfBounds(usize),
+    /// The position falls inside a multi-byte character.
+    NotCharBoundary(usize),
+    /// The edit touches a read-only region.
+    ReadOnly(usize),
+    /// The edit overlaps another edit in the same batch.
+    Overlapping(usize),
+    /// The anchor the edit is positioned by has been removed.
+    NoSuchAnchor,
+}
+
+impl fmt::Display for EditError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EditError::OutOfBounds(position) => {
+                write!(f, "position {} is past the end of the document", position)
+            }
+            EditError::NotCharBoundary(position) => {
+                write!(f, "position {} is not on a character boundary", position)
+            }
+            EditError::ReadOnly(position) => {
+                write!(f, "edit at {} touches a read-only region", position)
+            }
+            EditError::Overlapping(position) => {
+                write!(f, "edit at {} overlaps another edit", position)
+            }
+            EditError::NoSuchAnchor => write!(f, "the anchor has been removed"),
+        }
+    }
+}
+
+impl std::error::Error for EditError {}
+
+/// A single insert or delete, described by the byte position it applies at.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Edit {
+    Insert { pos: usize, text: String },
+    Delete { pos: usize, len: usize },
+}
+
+impl Edit {
+    /// The byte position the edit applies at.
+    fn pos(&self) -> usize {
+        match self {
+            Edit::Insert { pos, .. } | Edit::Delete { pos, .. } => *pos,
+        }
+    }
+
+    /// Where a byte position in a document ends up once this edit is applied to it.
+    /// An insert moves positions at or after it along past the inserted text, and a
+    /// delete moves positions inside the deleted text to its start and positions after
+    /// it back. The edit is assumed to fit the document, as no document is consulted.
+    pub fn rebase_position(&self, position: usize) -> usize {
+        match self {
+            Edit::Insert { pos, text } if position >= *pos => position + text.len(),
+            Edit::Delete { pos, len } if position > *pos => position - min(position - pos, *len),
+            _ => position,
+        }
+    }
+}
+
 impl PieceTable {
-    /// Creates a new empty piece table
+    /// Creates a new empty piece table, which remembers DEFAULT_HISTORY_LIMIT edits for
+    /// undo.
     pub fn new() -> Self {
         PieceTable {
             buffers: Vec::new(),
             pieces: Vec::new(),
+            max_pieces: None,
+            readonly_regions: Vec::new(),
+            line_count: OnceLock::new(),
+            len: OnceLock::new(),
+            piece_ends: OnceLock::new(),
+            normalize_eol: false,
+            binary: false,
+            original_len: None,
+            version: 0,
+            undo_stack: VecDeque::new(),
+            redo_stack: Vec::new(),
+            history_limit: DEFAULT_HISTORY_LIMIT,
+            anchors: Vec::new(),
+            buffer_growth: BufferGrowth::Proportional,
+            transaction_depth: 0,
+            transaction_start: None,
+            transaction_edited: false,
+            pending_change: None,
+            observers: Observers::default(),
+            #[cfg(test)]
+            splits: 0,
         }
     }
 
-    /// Creates a new piece table initialized with the specified string
+    /// Creates a builder for a piece table, as PieceTableBuilder::new does.
+    pub fn builder() -> PieceTableBuilder {
+        PieceTableBuilder::new()
+    }
+
+    /// Creates a new piece table initialized with the specified string.
+    /// An empty string gives a table without any pieces, as pieces are never empty.
     pub fn from_string(s: String) -> Self {
+        let piece = Piece {
+            buffer_index: 0,
+            start: 0,
+            end: s.len(),
+        };
         PieceTable {
-            pieces: vec![Piece {
-                buffer_index: 0,
+            pieces: if s.is_empty() {
+                Vec::new()
+            } else {
+                vec![piece]
+            },
+            original_len: Some(s.len()),
+            buffers: vec![Arc::new(s.into_bytes())],
+            ..Self::new()
+        }
+    }
+
+    /// Creates a new piece table initialized with the specified bytes, which needn't be
+    /// valid UTF-8. Unless they are, the table is binary, as for is_binary.
+    pub fn from_bytes(bytes: Vec<u8>) -> Self {
+        match String::from_utf8(bytes) {
+            Ok(text) => Self::from_string(text),
+            Err(error) => {
+                let bytes = error.into_bytes();
+                PieceTable {
+                    pieces: vec![Piece {
+                        buffer_index: 0,
+                        start: 0,
+                        end: bytes.len(),
+                    }],
+                    original_len: Some(bytes.len()),
+                    buffers: vec![Arc::new(bytes)],
+                    binary: true,
+                    ..Self::new()
+                }
+            }
+        }
+    }
+
+    /// Creates a new empty piece table with an add buffer that has room for at least
+    /// bytes bytes, so text streamed in through many small inserts is not reallocated.
+    /// Once that buffer is full, new buffers are added by the buffer growth policy as usual.
+    pub fn with_capacity(bytes: usize) -> Self {
+        PieceTable {
+            buffers: vec![Arc::new(Vec::with_capacity(bytes))],
+            pieces: Vec::with_capacity(2),
+            ..Self::new()
+        }
+    }
+
+    /// Creates a new piece table initialized with everything read from reader.
+    /// Any spare capacity left in the buffer it is read into is used for later inserts.
+    /// Returns an error of kind InvalidData if the text isn't valid UTF-8.
+    pub fn from_reader<R: Read>(mut reader: R) -> io::Result<Self> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        let text = String::from_utf8(bytes)
+            .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+        Ok(Self::from_string(text))
+    }
+
+    /// Creates a new empty piece table that remembers at most limit edits for undo,
+    /// forgetting the oldest edits once there are more. A limit of 0 turns undo off.
+    pub fn with_history_limit(limit: usize) -> Self {
+        PieceTable {
+            history_limit: limit,
+            ..Self::new()
+        }
+    }
+
+    /// Takes a copy of the document that shares its buffers with this table, so that only
+    /// the pieces are copied. Edits to either table go into new buffers rather than
+    /// extending the shared ones. The copy starts with no undo history.
+    pub fn snapshot(&self) -> PieceTable {
+        PieceTable {
+            buffers: self.buffers.clone(),
+            pieces: self.pieces.clone(),
+            max_pieces: self.max_pieces,
+            readonly_regions: self.readonly_regions.clone(),
+            normalize_eol: self.normalize_eol,
+            binary: self.binary,
+            original_len: self.original_len,
+            version: self.version,
+            history_limit: self.history_limit,
+            buffer_growth: self.buffer_growth,
+            ..Self::new()
+        }
+    }
+
+    /// A counter that increases every time the document is edited.
+    /// Comparing it with a previously recorded value shows whether the document
+    /// has been edited since.
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    /// A hash of the text of the document. Unlike version, it depends only on the text,
+    /// so documents with the same text hash the same however they are split into pieces,
+    /// and undoing an edit by hand restores the previous hash.
+    pub fn content_hash(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        self.bytes_from(0).for_each(|byte| hasher.write_u8(byte));
+        hasher.finish()
+    }
+
+    /// The number of pieces the table may hold before an edit forces it to
+    /// be compacted, or None if there is no limit.
+    pub fn max_pieces(&self) -> Option<usize> {
+        self.max_pieces
+    }
+
+    /// Limits the number of pieces the table may hold.
+    /// If an edit leaves more than max pieces behind, the whole document is
+    /// compacted into a single buffer and piece before the edit returns.
+    /// This stops pathological edit patterns from growing the piece list without
+    /// bound, but each compaction copies the entire document and forgets the undo
+    /// history, so the limit should be high enough that it is rarely hit.
+    pub fn set_max_pieces(&mut self, max: Option<usize>) {
+        self.max_pieces = max;
+        self.enforce_max_pieces();
+    }
+
+    /// Sets how big each new add buffer is made. Buffers that already exist are unchanged.
+    pub fn set_buffer_growth(&mut self, growth: BufferGrowth) {
+        self.buffer_growth = growth;
+    }
+
+    /// Called after every edit to the document to bump the version, discard anything
+    /// cached about its contents, keep the number of pieces within max_pieces and tell
+    /// the observers what changed.
+    /// The length is kept up to date by the edits themselves, through resized.
+    /// Inside a transaction only the caches are discarded, and the rest waits until the
+    /// transaction ends.
+    fn edited(&mut self) {
+        self.line_count.take();
+        self.piece_ends.take();
+        if self.transaction_depth > 0 {
+            self.transaction_edited = true;
+            return;
+        }
+        self.version += 1;
+        self.enforce_max_pieces();
+        self.notify_change();
+    }
+
+    /// Called before every edit to the document to remember its state for undo.
+    /// Any edits that were undone can no longer be redone.
+    /// Inside a transaction the state was already remembered when it started.
+    fn record_edit(&mut self) {
+        self.redo_stack.clear();
+        if self.transaction_depth == 0 {
+            let entry = self.history_entry();
+            self.push_undo(entry);
+        }
+    }
+
+    /// The current state of the document, as kept for undo.
+    fn history_entry(&self) -> HistoryEntry {
+        HistoryEntry {
+            pieces: self.pieces.clone(),
+            readonly_regions: self.readonly_regions.clone(),
+        }
+    }
+
+    /// Pushes a state onto the undo stack, dropping the oldest states beyond the limit.
+    fn push_undo(&mut self, entry: HistoryEntry) {
+        if self.history_limit == 0 {
+            return;
+        }
+        self.undo_stack.push_back(entry);
+        while self.undo_stack.len() > self.history_limit {
+            self.undo_stack.pop_front();
+        }
+    }
+
+    /// Runs f on the table as a single edit, so that one undo reverts everything f did,
+    /// the version goes up by one and observers are told about a single change covering
+    /// everything f changed once f returns.
+    /// If f panics, the document, its read-only regions and its anchors are put back
+    /// how they were before the panic carries on. Transactions can be nested, in which
+    /// case only the outermost one makes an undo step.
+    /// The table isn't compacted to keep within max_pieces until the transaction ends.
+    /// Compacting it explicitly inside the transaction forgets the history, including
+    /// the state the transaction started from, so nothing can be rolled back after that.
+    pub fn transaction<R>(&mut self, f: impl FnOnce(&mut Self) -> R) -> R {
+        if self.transaction_depth > 0 {
+            self.transaction_depth += 1;
+            let result = panic::catch_unwind(AssertUnwindSafe(|| f(self)));
+            self.transaction_depth -= 1;
+            return result.unwrap_or_else(|payload| panic::resume_unwind(payload));
+        }
+        self.transaction_start = Some(self.history_entry());
+        let anchors = self.anchors.clone();
+        let redo_stack = std::mem::take(&mut self.redo_stack);
+
+        self.transaction_depth = 1;
+        let result = panic::catch_unwind(AssertUnwindSafe(|| f(self)));
+        self.transaction_depth = 0;
+
+        let start = self.transaction_start.take();
+        let edited = std::mem::take(&mut self.transaction_edited);
+        match (result, start) {
+            (Err(payload), Some(start)) => {
+                self.swap_state(start);
+                self.pending_change = None;
+                self.anchors = anchors;
+                self.redo_stack = redo_stack;
+                panic::resume_unwind(payload)
+            }
+            (result, start) => {
+                if !edited {
+                    self.redo_stack = redo_stack;
+                } else {
+                    if let Some(start) = start {
+                        self.push_undo(start);
+                    }
+                    self.edited();
+                }
+                result.unwrap_or_else(|payload| panic::resume_unwind(payload))
+            }
+        }
+    }
+
+    /// Restores the document to how it was before the most recent edit.
+    /// Read-only regions are restored along with the text.
+    /// Returns false if there is nothing to undo.
+    pub fn undo(&mut self) -> bool {
+        match self.undo_stack.pop_back() {
+            Some(entry) => {
+                let current = self.restore(entry);
+                self.redo_stack.push(current);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Reapplies the most recently undone edit.
+    /// Returns false if there is nothing to redo, including after any new edit.
+    pub fn redo(&mut self) -> bool {
+        match self.redo_stack.pop() {
+            Some(entry) => {
+                let current = self.restore(entry);
+                self.undo_stack.push_back(current);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Puts the document back into the state held by entry, returning its current state.
+    /// Observers are told the whole document was replaced.
+    fn restore(&mut self, entry: HistoryEntry) -> HistoryEntry {
+        let removed = self.len();
+        let current = self.swap_state(entry);
+        self.pending_change = Some(Change {
+            removed: 0..removed,
+            inserted: 0..self.len(),
+        });
+        self.edited();
+        current
+    }
+
+    /// Puts the document back into the state held by entry without counting it as an
+    /// edit, returning its current state. Anchors are kept within the document.
+    fn swap_state(&mut self, entry: HistoryEntry) -> HistoryEntry {
+        let current = HistoryEntry {
+            pieces: std::mem::replace(&mut self.pieces, entry.pieces),
+            readonly_regions: std::mem::replace(&mut self.readonly_regions, entry.readonly_regions),
+        };
+        self.len.take();
+        self.line_count.take();
+        self.piece_ends.take();
+        let len = self.len();
+        for anchor in self.anchors.iter_mut().flatten() {
+            anchor.position = min(anchor.position, len);
+        }
+        current
+    }
+
+    /// Compacts the table if it holds more pieces than max_pieces allows, unless it is in
+    /// the middle of a transaction.
+    fn enforce_max_pieces(&mut self) {
+        if self.transaction_depth > 0 {
+            return;
+        }
+        if let Some(max) = self.max_pieces {
+            if self.pieces.len() > max {
+                self.compact();
+            }
+        }
+    }
+
+    /// Rebuilds the table so that the document is held in a single buffer
+    /// referenced by a single piece, dropping any text no longer in use.
+    /// Returns the number of bytes of buffer text dropped, as counted by buffer_bytes
+    /// rather than buffer_capacity.
+    /// This copies the whole document and forgets the undo history.
+    pub fn compact(&mut self) -> usize {
+        let before = self.buffer_bytes();
+        self.compact_with_chunk_size(usize::MAX);
+        before - self.len()
+    }
+
+    /// Rebuilds the table so that the document is held in as few buffers as possible
+    /// of at most chunk_bytes each, each referenced by a single piece, dropping any text
+    /// no longer in use. Smaller chunks avoid one enormous allocation for a large document
+    /// at the cost of more pieces.
+    /// Buffers are only split on character boundaries, so a buffer can only exceed
+    /// chunk_bytes if it holds a single character larger than that. Binary tables are
+    /// split wherever the chunks end.
+    /// The old buffers are dropped, so the undo history is forgotten.
+    pub fn compact_with_chunk_size(&mut self, chunk_bytes: usize) {
+        let mut remaining = self.len();
+        let mut buffers = Vec::new();
+        let mut buffer = Vec::new();
+        for piece in &self.pieces {
+            let mut text = self.piece_slice(*piece);
+            while !text.is_empty() {
+                if buffer.capacity() == 0 {
+                    buffer.reserve_exact(min(chunk_bytes, remaining));
+                }
+                let mut split = min(chunk_bytes.saturating_sub(buffer.len()), text.len());
+                while !self.binary && !is_utf8_boundary(text, split) {
+                    split -= 1;
+                }
+                if split == 0 && buffer.is_empty() {
+                    split = (1..=text.len())
+                        .find(|end| self.binary || is_utf8_boundary(text, *end))
+                        .unwrap_or(text.len());
+                }
+                buffer.extend_from_slice(&text[..split]);
+                text = &text[split..];
+                remaining -= split;
+                if split == 0 || buffer.len() >= chunk_bytes {
+                    buffers.push(std::mem::take(&mut buffer));
+                }
+            }
+        }
+        if !buffer.is_empty() {
+            buffers.push(buffer);
+        }
+        self.pieces = buffers
+            .iter()
+            .enumerate()
+            .map(|(buffer_index, buffer)| Piece {
+                buffer_index,
                 start: 0,
-                end: s.len(),
-            }],
-            buffers: vec![s],
+                end: buffer.len(),
+            })
+            .collect();
+        self.buffers = buffers.into_iter().map(Arc::new).collect();
+        self.original_len = None;
+        self.piece_ends.take();
+        self.forget_history();
+    }
+
+    /// Rebuilds the table so that all of the text that isn't original is held in a single
+    /// add buffer, while pieces of original text keep referencing the original buffer,
+    /// so that change_regions reports the same changes afterwards.
+    /// Without any original text this is the same as compact.
+    /// The old add buffers are dropped, so the undo history is forgotten.
+    pub fn compact_preserving_origin(&mut self) {
+        let Some(original_len) = self.original_len else {
+            self.compact();
+            return;
+        };
+        let mut added = Vec::new();
+        let mut pieces: Vec<Piece> = Vec::new();
+        for piece in &self.pieces {
+            let split = match piece.buffer_index {
+                0 => min(piece.len(), original_len.saturating_sub(piece.start)),
+                _ => 0,
+            };
+            let start = added.len();
+            added.extend_from_slice(&self.piece_slice(*piece)[split..]);
+            let changed = Piece {
+                buffer_index: 1,
+                start,
+                end: added.len(),
+            };
+            for part in [piece.before(split), changed] {
+                if part.len() > 0 && !pieces.last_mut().is_some_and(|last| last.merge(part)) {
+                    pieces.push(part);
+                }
+            }
+        }
+        let mut original = std::mem::take(&mut self.buffers[0]);
+        if let Some(original) = Arc::get_mut(&mut original) {
+            original.truncate(original_len);
+        }
+        self.buffers = vec![original];
+        if !added.is_empty() {
+            self.buffers.push(Arc::new(added));
+        }
+        self.pieces = pieces;
+        self.piece_ends.take();
+        self.forget_history();
+    }
+
+    /// Forgets the undo history, including the state any transaction started from, once
+    /// the buffers the history refers to have been replaced.
+    fn forget_history(&mut self) {
+        self.undo_stack.clear();
+        self.redo_stack.clear();
+        self.transaction_start = None;
+    }
+
+    /// Releases any spare capacity held by the list of pieces.
+    /// This is useful after a heavily fragmented document has been cut down,
+    /// as the list otherwise keeps the capacity it needed at its largest.
+    pub fn shrink_pieces(&mut self) {
+        self.pieces.shrink_to_fit();
+    }
+
+    /// Sets whether insert converts line endings to '\n'.
+    /// When on, every "\r\n" and lone '\r' in inserted text is stored as '\n'.
+    /// It is off by default, and doesn't affect text already in the document.
+    pub fn set_normalize_eol(&mut self, on: bool) {
+        self.normalize_eol = on;
+    }
+
+    /// Pins a range of the document as read-only.
+    /// Any insert strictly inside the range, or delete overlapping it, is rejected with
+    /// EditError::ReadOnly and leaves the document unchanged. Inserting at either end of the range is
+    /// allowed. Edits before the range move it so that it keeps covering the same text.
+    pub fn add_readonly_region(&mut self, range: Range<usize>) {
+        if range.start < range.end {
+            self.readonly_regions.push(range);
+        }
+    }
+
+    /// Ensures the latest add buffer has room for at least additional more bytes, and that
+    /// the list of pieces has room for the pieces an insert adds.
+    /// The amount is a minimum rather than being added to what the growth policy would
+    /// give: if a new add buffer is needed it is sized by the buffer growth policy, as
+    /// every new buffer is, and only made bigger if the policy gives less than additional.
+    /// The original buffer and buffers shared with other tables are never grown, a new
+    /// add buffer is created instead.
+    pub fn reserve(&mut self, additional: usize) {
+        self.pieces.reserve(2);
+        match self.buffers.len().checked_sub(1) {
+            Some(last) if !self.is_original_buffer(last) && !self.is_shared_buffer(last) => {
+                Arc::make_mut(&mut self.buffers[last]).reserve(additional);
+            }
+            _ => self.add_buffer(additional),
+        }
+    }
+
+    /// Ensures the latest add buffer has room for at least additional more bytes,
+    /// allocating exactly that much rather than following the buffer growth policy.
+    /// The original buffer and buffers shared with other tables are never grown, a new
+    /// add buffer is created instead.
+    pub fn reserve_exact(&mut self, additional: usize) {
+        match self.buffers.len().checked_sub(1) {
+            Some(last) if !self.is_original_buffer(last) && !self.is_shared_buffer(last) => {
+                Arc::make_mut(&mut self.buffers[last]).reserve_exact(additional);
+            }
+            _ => self.buffers.push(Arc::new(Vec::with_capacity(additional))),
         }
     }
 
-    /// Adds a new buffer to the piece table with at least the same capacity
-    /// as all the other buffers put together.
+    /// Adds a new buffer to the piece table with at least min_capacity, sized by the
+    /// buffer growth policy. Proportional growth gives it the same capacity as all the
+    /// other buffers put together, not counting buffers shared with other tables, so that
+    /// every snapshot doesn't double the memory used by the next buffer.
     fn add_buffer(&mut self, min_capacity: usize) {
-        let buffer = String::with_capacity(max(
-            min_capacity,
-            self.buffers
+        let capacity = match self.buffer_growth {
+            BufferGrowth::Proportional => self
+                .buffers
                 .iter()
+                .filter(|buffer| Arc::strong_count(buffer) == 1)
                 .fold(0, |sum, buffer| sum + buffer.len()),
-        ));
-        self.buffers.push(buffer);
+            BufferGrowth::Fixed(size) => size,
+        };
+        let buffer = Vec::with_capacity(max(min_capacity, capacity));
+        self.buffers.push(Arc::new(buffer));
     }
 
     /// Find the location of a piece in the piece table.
+    /// A position at the end of a piece is located at the start of the next piece.
     fn locate(&self, position: usize) -> Location {
-        let mut offset = 0;
-        for (index, piece) in self.pieces.iter().copied().enumerate() {
-            offset += piece.len();
-            if position < offset {
-                return Location::new(index, piece.len() - (offset - position));
-            }
-            if position == offset {
-                return Location::new(index + 1, 0);
-            }
+        let ends = self.piece_ends();
+        let index = ends.partition_point(|end| *end <= position);
+        match self.pieces.get(index) {
+            Some(piece) => Location::new(index, position + piece.len() - ends[index]),
+            None => Location::new(self.pieces.len(), 0),
         }
-        return Location::new(self.pieces.len(), 0);
+    }
+
+    /// The document offset of the end of each piece, so that locate can binary search
+    /// for the piece containing a position. It is built the first time it is needed and
+    /// discarded whenever the pieces change.
+    fn piece_ends(&self) -> &[usize] {
+        self.piece_ends.get_or_init(|| {
+            self.pieces
+                .iter()
+                .scan(0, |end, piece| {
+                    *end += piece.len();
+                    Some(*end)
+                })
+                .collect()
+        })
     }
 
     /// Split a piece in two at the specified point if necessary.
@@ -139,6 +838,11 @@ impl PieceTable {
     ///
     /// The return value is the insertion index required to insert a new piece in the gap.
     fn split(&mut self, loc: Location, gap: usize) -> usize {
+        self.piece_ends.take();
+        #[cfg(test)]
+        {
+            self.splits += 1;
+        }
         if let Some(piece) = self.pieces.get(loc.piece_index).copied() {
             if loc.offset == 0 {
                 let after = piece.after(gap);
@@ -162,24 +866,202 @@ impl PieceTable {
         loc.piece_index
     }
 
+    /// Checks whether the buffer at index holds the original text the table was created with.
+    fn is_original_buffer(&self, index: usize) -> bool {
+        index == 0 && self.original_len.is_some()
+    }
+
+    /// Checks whether the buffer at index is shared with another table, so can't be extended.
+    fn is_shared_buffer(&self, index: usize) -> bool {
+        Arc::strong_count(&self.buffers[index]) > 1
+    }
+
     // Retrieves a buffer with at least the specified capacity.
-    fn buffer_with_capacity(&mut self, capacity: usize) -> (usize, &mut Buffer) {
+    // This may be the original buffer, if it was created with spare capacity.
+    fn buffer_with_capacity(&mut self, capacity: usize) -> (usize, &mut Vec<u8>) {
         if self
             .buffers
-            .last_mut()
-            .filter(|buffer| buffer.capacity() - buffer.len() > capacity)
+            .last()
+            .filter(|buffer| Arc::strong_count(buffer) == 1)
+            .filter(|buffer| buffer.capacity() - buffer.len() >= capacity)
             .is_none()
         {
             self.add_buffer(capacity);
         }
-        (self.buffers.len() - 1, self.buffers.last_mut().unwrap())
+        let last = self.buffers.len() - 1;
+        (last, Arc::make_mut(&mut self.buffers[last]))
+    }
+
+    // Finds the buffer to extend if an insert at loc continues a run of typing, that is
+    // if the piece before loc ends at the end of the latest add buffer.
+    // The original buffer is only ever extended into its spare capacity, as growing it
+    // would mean copying the whole of the original text.
+    // Extending that buffer, even past its capacity, lets the inserted text merge into
+    // that piece. Switching to a new buffer part way through a run would leave the run
+    // split into two pieces, as pieces in different buffers can never merge.
+    fn run_buffer(&self, loc: Location) -> Option<usize> {
+        if loc.offset > 0 || loc.piece_index == 0 {
+            return None;
+        }
+        let piece = self.pieces[loc.piece_index - 1];
+        let last = self.buffers.len() - 1;
+        if piece.buffer_index == last
+            && !self.is_original_buffer(last)
+            && !self.is_shared_buffer(last)
+            && piece.end == self.buffers[last].len()
+        {
+            Some(last)
+        } else {
+            None
+        }
+    }
+
+    /// Inserts s at the specified byte position, returning the byte range the inserted
+    /// text occupies, which may differ in length from s if line endings are normalized.
+    /// Returns an error without changing the document if the position is past the end
+    /// of the document, falls inside a character, or is inside a read-only region.
+    pub fn insert(
+        &mut self,
+        position: usize,
+        s: impl AsRef<str>,
+    ) -> Result<Range<usize>, EditError> {
+        if position > self.len() {
+            return Err(EditError::OutOfBounds(position));
+        }
+        self.insert_clamped(position, s)
+    }
+
+    /// Inserts a single character at the specified byte position, without needing a
+    /// string to hold it. Returns the byte range the character occupies, or an error in
+    /// the same cases as insert.
+    pub fn insert_char(&mut self, position: usize, c: char) -> Result<Range<usize>, EditError> {
+        self.insert(position, c.encode_utf8(&mut [0; 4]))
+    }
+
+    /// Inserts s at the specified byte position, or at the end of the document if the
+    /// position is past it, returning the byte range the inserted text occupies.
+    /// Returns an error without changing the document if the position falls inside a
+    /// character or is inside a read-only region.
+    pub fn insert_clamped(
+        &mut self,
+        position: usize,
+        s: impl AsRef<str>,
+    ) -> Result<Range<usize>, EditError> {
+        let s = self.normalize(s.as_ref());
+        let position = min(position, self.len());
+        self.check_insert(position)?;
+        Ok(self.insert_unchecked(position, s.as_bytes()))
+    }
+
+    /// Inserts bytes at the specified byte position, returning the byte range they
+    /// occupy. The bytes needn't be valid UTF-8 and the position needn't be on a character
+    /// boundary, and line endings are never normalized. If the bytes aren't valid UTF-8 or
+    /// the position splits a character, the table becomes binary, as for is_binary.
+    /// Returns an error without changing the document if the position is past the end
+    /// of the document or is inside a read-only region.
+    pub fn insert_bytes(
+        &mut self,
+        position: usize,
+        bytes: &[u8],
+    ) -> Result<Range<usize>, EditError> {
+        if position > self.len() {
+            return Err(EditError::OutOfBounds(position));
+        }
+        self.check_writable(position)?;
+        if std::str::from_utf8(bytes).is_err() || !self.is_char_boundary(position) {
+            self.binary = true;
+        }
+        Ok(self.insert_unchecked(position, bytes))
+    }
+
+    /// Inserts bytes at a position that has already been checked, returning the byte
+    /// range they occupy.
+    fn insert_unchecked(&mut self, position: usize, s: &[u8]) -> Range<usize> {
+        if s.is_empty() {
+            return position..position;
+        }
+        self.record_edit();
+
+        let index = self.split(self.locate(position), 0);
+        self.insert_piece(index, s);
+        self.resized(s.len(), 0);
+        self.text_changed(position, 0, s.len());
+        for region in &mut self.readonly_regions {
+            if position <= region.start {
+                region.start += s.len();
+                region.end += s.len();
+            }
+        }
+        self.edited();
+        position..position + s.len()
+    }
+
+    /// Checks whether the table is binary, which it becomes once bytes that aren't valid
+    /// UTF-8 are added with from_bytes or insert_bytes, or a character is split with
+    /// insert_bytes or delete_bytes. It stays binary even if the document later holds
+    /// valid UTF-8 again. The byte methods, such as to_bytes and bytes, work on any table,
+    /// but the methods that produce text panic on a binary table if the text isn't valid
+    /// UTF-8. Displaying a binary table writes invalid bytes as U+FFFD instead.
+    pub fn is_binary(&self) -> bool {
+        self.binary
+    }
+
+    /// Checks that text can be inserted at a position no further than the end of the
+    /// document, which must be on a character boundary and not inside a read-only region.
+    fn check_insert(&self, position: usize) -> Result<(), EditError> {
+        if !self.is_char_boundary(position) {
+            return Err(EditError::NotCharBoundary(position));
+        }
+        self.check_writable(position)
+    }
+
+    /// Checks that a position no further than the end of the document isn't inside a
+    /// read-only region, so that bytes can be inserted there.
+    fn check_writable(&self, position: usize) -> Result<(), EditError> {
+        if self
+            .readonly_regions
+            .iter()
+            .any(|region| region.start < position && position < region.end)
+        {
+            return Err(EditError::ReadOnly(position));
+        }
+        Ok(())
+    }
+
+    /// Converts line endings in s to '\n' if the table normalizes them.
+    fn normalize<'a>(&self, s: &'a str) -> Cow<'a, str> {
+        if self.normalize_eol && s.contains('\r') {
+            Cow::Owned(s.replace("\r\n", "\n").replace('\r', "\n"))
+        } else {
+            Cow::Borrowed(s)
+        }
     }
 
-    pub fn insert(&mut self, position: usize, s: &str) {
-        let (buffer_index, buffer) = self.buffer_with_capacity(s.len());
+    /// Adds s to a buffer and inserts a piece referencing it at index in the piece list,
+    /// merging it into the piece before if that continues a run of typing.
+    /// If s is exactly the text deleted from between the pieces either side of index,
+    /// those pieces are joined back together instead, without adding s to a buffer.
+    fn insert_piece(&mut self, index: usize, s: &[u8]) {
+        if let (Some(before), Some(after)) = (
+            index.checked_sub(1).map(|before| self.pieces[before]),
+            self.pieces.get(index).copied(),
+        ) {
+            if before.buffer_index == after.buffer_index
+                && after.start.checked_sub(before.end) == Some(s.len())
+                && self.buffers[before.buffer_index][before.end..after.start] == *s
+            {
+                self.pieces[index - 1].end = after.end;
+                self.pieces.remove(index);
+                return;
+            }
+        }
+        let (buffer_index, buffer) = match self.run_buffer(Location::new(index, 0)) {
+            Some(index) => (index, Arc::make_mut(&mut self.buffers[index])),
+            None => self.buffer_with_capacity(s.len()),
+        };
         let start = buffer.len();
         let end = start + s.len();
-        *buffer += s;
+        buffer.extend_from_slice(s);
 
         let piece = Piece {
             buffer_index,
@@ -187,17 +1069,99 @@ impl PieceTable {
             end,
         };
 
-        let loc = self.locate(position);
-        let index = self.split(loc, 0);
-
         if index == 0 || !self.pieces[index - 1].merge(piece) {
             self.pieces.insert(index, piece);
         }
     }
 
-    pub fn delete(&mut self, position: usize, mut len: usize) {
+    /// Checks that len bytes starting at position can be deleted, returning the end of the
+    /// deletion clamped to the end of the document.
+    fn deletion_end(&self, position: usize, len: usize) -> Result<usize, EditError> {
+        let end = self.clamped_end(position, len)?;
+        if let Some(offset) = [position, end]
+            .iter()
+            .find(|offset| !self.is_char_boundary(**offset))
+        {
+            return Err(EditError::NotCharBoundary(*offset));
+        }
+        self.check_deletable(position, end)?;
+        Ok(end)
+    }
+
+    /// The end of len bytes starting at position, clamped to the end of the document.
+    /// Returns an error if the position is past the end of the document.
+    fn clamped_end(&self, position: usize, len: usize) -> Result<usize, EditError> {
+        let document_len = self.len();
+        if position > document_len {
+            return Err(EditError::OutOfBounds(position));
+        }
+        Ok(min(position.saturating_add(len), document_len))
+    }
+
+    /// Checks that the bytes from position to end don't overlap a read-only region.
+    fn check_deletable(&self, position: usize, end: usize) -> Result<(), EditError> {
+        if self
+            .readonly_regions
+            .iter()
+            .any(|region| position < region.end && region.start < end)
+        {
+            return Err(EditError::ReadOnly(position));
+        }
+        Ok(())
+    }
+
+    /// Deletes len bytes starting at the specified byte position. If that would run past
+    /// the end of the document, everything from the position onwards is deleted.
+    /// Returns an error without changing the document if the position is past the end of
+    /// the document, either end of the deletion falls inside a character, or the deletion
+    /// overlaps a read-only region.
+    pub fn delete(&mut self, position: usize, len: usize) -> Result<(), EditError> {
+        let end = self.deletion_end(position, len)?;
+        self.delete_unchecked(position, end);
+        Ok(())
+    }
+
+    /// Deletes len bytes starting at the specified byte position, as delete does, except
+    /// that either end may fall inside a character, which makes the table binary, as for
+    /// is_binary.
+    /// Returns an error without changing the document if the position is past the end of
+    /// the document or the deletion overlaps a read-only region.
+    pub fn delete_bytes(&mut self, position: usize, len: usize) -> Result<(), EditError> {
+        let end = self.clamped_end(position, len)?;
+        self.check_deletable(position, end)?;
+        if !self.is_char_boundary(position) || !self.is_char_boundary(end) {
+            self.binary = true;
+        }
+        self.delete_unchecked(position, end);
+        Ok(())
+    }
+
+    /// Deletes the bytes from position to end, which have already been checked.
+    fn delete_unchecked(&mut self, position: usize, end: usize) {
+        let mut len = end - position;
+        if len == 0 {
+            return;
+        }
+        self.record_edit();
+        self.resized(0, len);
+        self.text_changed(position, len, 0);
+        for region in &mut self.readonly_regions {
+            if end <= region.start {
+                region.start -= len;
+                region.end -= len;
+            }
+        }
+
         let mut pos = self.locate(position);
 
+        // Deleting exactly one whole piece needs no splitting.
+        if pos.offset == 0 && self.pieces.get(pos.piece_index).map(Piece::len) == Some(len) {
+            self.pieces.remove(pos.piece_index);
+            self.merge_at(pos.piece_index);
+            self.edited();
+            return;
+        }
+
         if pos.offset > 0 {
             let gap = min(len, self.pieces[pos.piece_index].len() - pos.offset);
             self.split(pos, len);
@@ -211,140 +1175,5479 @@ impl PieceTable {
             self.split(pos, gap);
             len -= gap;
         }
+        self.merge_at(pos.piece_index);
+        self.edited();
     }
 
-    fn piece_text(&self, piece: Piece) -> &str {
-        &self.buffers[piece.buffer_index][piece.start..piece.end]
+    /// Deletes the bytes in the specified range, which may be any kind of range such as
+    /// start..end, start..=end, start.. or .., with a missing end meaning the end of the
+    /// document. As with delete, an end past the end of the document is clamped to it.
+    /// Returns an error without changing the document in the same cases as delete.
+    pub fn delete_range(&mut self, range: impl RangeBounds<usize>) -> Result<(), EditError> {
+        let start = match range.start_bound() {
+            Bound::Included(start) => *start,
+            Bound::Excluded(start) => start.saturating_add(1),
+            Bound::Unbounded => 0,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(end) => end.saturating_add(1),
+            Bound::Excluded(end) => *end,
+            Bound::Unbounded => self.len(),
+        };
+        self.delete(start, end.saturating_sub(start))
     }
 
-    pub fn to_string(&self) -> String {
-        self.pieces.iter().fold(String::new(), |mut s, piece| {
-            s += self.piece_text(*piece);
-            s
-        })
+    /// Gets the text that delete would remove given the same arguments, without changing
+    /// the document. As with delete, len is clamped to the end of the document.
+    /// Returns the error delete would return in the cases it would reject the deletion.
+    pub fn peek_delete(&self, position: usize, len: usize) -> Result<String, EditError> {
+        let end = self.deletion_end(position, len)?;
+        Ok(self.range_chunks(position..end).collect())
     }
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
 
-    #[test]
-    fn new_should_create_a_piece_table_with_no_buffers_or_pieces() {
-        let piece_table = PieceTable::new();
+    /// Replaces len bytes starting at the specified byte position with s, as a single edit
+    /// that only has to find the position once. A zero len makes this an insert, and an
+    /// empty s makes it a delete.
+    /// Returns an error without changing the document in the same cases as delete.
+    pub fn replace(&mut self, position: usize, len: usize, s: &str) -> Result<(), EditError> {
+        if len == 0 {
+            return self.insert(position, s).map(|_| ());
+        }
+        if s.is_empty() {
+            return self.delete(position, len);
+        }
+        let end = self.deletion_end(position, len)?;
+        let s = self.normalize(s);
+        self.record_edit();
+        let removed = end - position;
+        for region in &mut self.readonly_regions {
+            if end <= region.start {
+                region.start = region.start - removed + s.len();
+                region.end = region.end - removed + s.len();
+            }
+        }
 
-        assert_eq!(piece_table.pieces.len(), 0);
-        assert_eq!(piece_table.buffers.len(), 0);
+        let index = self.split(self.locate(position), 0);
+        self.remove_bytes(index, removed);
+        self.insert_piece(index, s.as_bytes());
+        self.resized(s.len(), removed);
+        self.text_changed(position, removed, s.len());
+        self.edited();
+        Ok(())
+    }
+
+    /// Removes len bytes from the start of the piece at index onwards, where a piece starts.
+    fn remove_bytes(&mut self, index: usize, len: usize) {
+        let mut remaining = len;
+        while remaining > 0 {
+            let piece = self.pieces[index];
+            if piece.len() > remaining {
+                self.pieces[index] = piece.after(remaining);
+                break;
+            }
+            self.pieces.remove(index);
+            remaining -= piece.len();
+        }
+    }
+
+    /// Writes s over the text starting at the specified byte position, replacing as many
+    /// bytes as s holds rather than shifting the text after it along. The document only
+    /// grows if s runs past the end of it.
+    /// Returns an error without changing the document in the same cases as delete,
+    /// including when the overwritten text would end inside a character.
+    pub fn overwrite(&mut self, position: usize, s: &str) -> Result<(), EditError> {
+        let len = self.normalize(s).len();
+        self.replace(position, len, s)
+    }
+
+    /// Replaces the text in the specified byte range with the text f makes from it, as a
+    /// single edit. As with delete, an end past the end of the document is clamped to it.
+    /// Returns an error without calling f or changing the document in the same cases
+    /// as delete.
+    pub fn map_range<F: FnOnce(&str) -> String>(
+        &mut self,
+        range: Range<usize>,
+        f: F,
+    ) -> Result<(), EditError> {
+        let start = range.start;
+        let end = self.deletion_end(start, range.end.saturating_sub(start))?;
+        let old: String = self.range_chunks(start..end).collect();
+        self.replace(start, end - start, &f(&old))
+    }
+
+    /// Merges the piece at index into the piece before it, if they reference
+    /// contiguous bytes of the same buffer.
+    fn merge_at(&mut self, index: usize) {
+        if index > 0 && index < self.pieces.len() {
+            let piece = self.pieces[index];
+            if self.pieces[index - 1].merge(piece) {
+                self.pieces.remove(index);
+            }
+        }
+    }
+
+    /// The text of a piece.
+    /// Panics if the piece isn't valid UTF-8, which only a binary table can hold.
+    fn piece_text(&self, piece: Piece) -> &str {
+        std::str::from_utf8(self.piece_slice(piece)).expect("piece isn't valid UTF-8")
+    }
+
+    /// The bytes of a piece.
+    fn piece_slice(&self, piece: Piece) -> &[u8] {
+        &self.buffers[piece.buffer_index][piece.start..piece.end]
+    }
+
+    /// Checks whether the document holds exactly the text expected, comparing it piece by
+    /// piece without allocating.
+    pub fn assert_text_eq(&self, expected: &str) -> bool {
+        self.content_eq_str(expected)
+    }
+
+    /// Checks whether the document holds exactly the text s, comparing it piece by piece
+    /// without allocating and stopping at the first difference. Documents of a different
+    /// length are rejected without comparing any text.
+    pub fn content_eq_str(&self, s: &str) -> bool {
+        self.len() == s.len() && self.bytes().eq(s.bytes())
+    }
+
+    /// Checks whether the document starts with prefix, without allocating.
+    pub fn starts_with(&self, prefix: &str) -> bool {
+        prefix.len() <= self.len() && self.bytes().take(prefix.len()).eq(prefix.bytes())
+    }
+
+    /// The byte offset of the first byte where the document differs from other, or None
+    /// if they hold the same text. If one is a prefix of the other, they differ at the end
+    /// of the shorter.
+    pub fn first_difference(&self, other: &str) -> Option<usize> {
+        let mut bytes = self.bytes().zip(other.bytes());
+        match bytes.position(|(a, b)| a != b) {
+            Some(offset) => Some(offset),
+            None if self.len() == other.len() => None,
+            None => Some(min(self.len(), other.len())),
+        }
+    }
+
+    /// The byte offset just past the last byte where the document differs from other,
+    /// counting from the end of each, given as an offset into the document. Returns None
+    /// if they hold the same text.
+    pub fn last_difference(&self, other: &str) -> Option<usize> {
+        let len = self.len();
+        let common = self
+            .pieces
+            .iter()
+            .rev()
+            .flat_map(|piece| self.piece_slice(*piece).iter().rev().copied())
+            .zip(other.bytes().rev())
+            .take_while(|(a, b)| a == b)
+            .count();
+        if common == len && len == other.len() {
+            None
+        } else {
+            Some(len - common)
+        }
+    }
+
+    /// Iterates over the bytes of the document, without allocating.
+    pub fn bytes(&self) -> impl Iterator<Item = u8> + '_ {
+        self.bytes_from(0)
+    }
+
+    /// Copies the bytes of the document into a vector.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.len());
+        for piece in &self.pieces {
+            bytes.extend_from_slice(self.piece_slice(*piece));
+        }
+        bytes
+    }
+
+    /// Iterates over the bytes of the document, starting at the specified position.
+    fn bytes_from(&self, position: usize) -> impl Iterator<Item = u8> + Clone + '_ {
+        let loc = self.locate(position);
+        self.pieces[loc.piece_index..]
+            .iter()
+            .enumerate()
+            .flat_map(move |(index, piece)| {
+                let skip = if index == 0 { loc.offset } else { 0 };
+                self.piece_slice(*piece)[skip..].iter().copied()
+            })
+    }
+
+    /// Length of the document in bytes.
+    /// This is counted once and then kept up to date by each edit, so calls don't walk
+    /// the pieces.
+    pub fn len(&self) -> usize {
+        *self
+            .len
+            .get_or_init(|| self.pieces.iter().map(Piece::len).sum())
+    }
+
+    /// Updates the remembered length of the document after an edit added and removed
+    /// the specified numbers of bytes. If the length hasn't been counted yet, it is left
+    /// to be counted when it is next needed.
+    fn resized(&mut self, added: usize, removed: usize) {
+        if let Some(len) = self.len.get_mut() {
+            *len = *len + added - removed;
+        }
+    }
+
+    /// Checks whether the document holds no text.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Checks whether the specified position lies on a character boundary.
+    /// The start and end of the document are boundaries, positions past the end aren't.
+    /// A binary table has no characters, so every position within it is a boundary.
+    fn is_char_boundary(&self, position: usize) -> bool {
+        if self.binary {
+            return position <= self.len();
+        }
+        let loc = self.locate(position);
+        match self.pieces.get(loc.piece_index) {
+            Some(piece) => is_utf8_boundary(self.piece_slice(*piece), loc.offset),
+            None => position == self.len(),
+        }
+    }
+
+    /// The byte at the specified position, or None if the position is at or past the end
+    /// of the document.
+    pub fn byte_at(&self, position: usize) -> Option<u8> {
+        let loc = self.locate(position);
+        let piece = self.pieces.get(loc.piece_index)?;
+        Some(self.piece_slice(*piece)[loc.offset])
+    }
+
+    /// The character starting at the specified byte position, or None if the position is
+    /// at or past the end of the document or isn't on a character boundary.
+    pub fn char_at(&self, position: usize) -> Option<char> {
+        let loc = self.locate(position);
+        let piece = self.pieces.get(loc.piece_index)?;
+        self.piece_text(*piece).get(loc.offset..)?.chars().next()
+    }
+
+    /// Iterates over the pieces from the specified piece index onwards, along with
+    /// the document offset each starts at. start is the document offset of the
+    /// piece at index.
+    fn pieces_from(
+        &self,
+        index: usize,
+        start: usize,
+    ) -> impl Iterator<Item = (usize, Piece)> + Clone + '_ {
+        self.pieces[index..].iter().scan(start, |offset, piece| {
+            let start = *offset;
+            *offset += piece.len();
+            Some((start, *piece))
+        })
+    }
+
+    /// Iterates over the characters of the document, without allocating.
+    pub fn chars(&self) -> impl Iterator<Item = char> + '_ {
+        self.chunks().flat_map(str::chars)
+    }
+
+    /// Iterates over every run of n consecutive characters in the document, in order,
+    /// with each run overlapping the one before in all but its first character.
+    /// Yields nothing if n is zero or the document has fewer than n characters.
+    pub fn char_windows(&self, n: usize) -> impl Iterator<Item = String> + '_ {
+        let mut chars = self.chars();
+        let mut window = VecDeque::with_capacity(n);
+        std::iter::from_fn(move || {
+            if n == 0 {
+                return None;
+            }
+            while window.len() < n {
+                window.push_back(chars.next()?);
+            }
+            let text = window.iter().collect();
+            window.pop_front();
+            Some(text)
+        })
+    }
+
+    /// Iterates over the characters of the document along with their byte offsets from
+    /// the start of the document, so that the offsets can be passed to insert and delete.
+    pub fn char_indices(&self) -> impl Iterator<Item = (usize, char)> + '_ {
+        self.char_indices_from(0)
+    }
+
+    /// Iterates over the characters of the document and their byte offsets,
+    /// starting at the specified position.
+    fn char_indices_from(
+        &self,
+        position: usize,
+    ) -> impl Iterator<Item = (usize, char)> + Clone + '_ {
+        let loc = self.locate(position);
+        self.pieces_from(loc.piece_index, position - loc.offset)
+            .flat_map(move |(start, piece)| {
+                let skip = position.saturating_sub(start);
+                self.piece_text(piece)[skip..]
+                    .char_indices()
+                    .map(move |(index, c)| (start + skip + index, c))
+            })
+    }
+
+    /// Iterates backwards over the characters of the document that come before the
+    /// specified position, along with their byte offsets.
+    fn rev_char_indices_before(&self, position: usize) -> impl Iterator<Item = (usize, char)> + '_ {
+        let position = min(position, self.len());
+        let loc = self.locate(position);
+        let (end, end_offset) = match self.pieces.get(loc.piece_index) {
+            Some(piece) if loc.offset > 0 => {
+                (loc.piece_index + 1, position - loc.offset + piece.len())
+            }
+            _ => (loc.piece_index, position),
+        };
+        self.pieces[..end]
+            .iter()
+            .rev()
+            .scan(end_offset, |offset, piece| {
+                *offset -= piece.len();
+                Some((*offset, *piece))
+            })
+            .flat_map(move |(start, piece)| {
+                self.piece_text(piece)
+                    .char_indices()
+                    .rev()
+                    .map(move |(index, c)| (start + index, c))
+                    .filter(move |(offset, _)| *offset < position)
+            })
+    }
+
+    /// Iterates over the text slices making up the specified byte range of the document.
+    fn range_chunks(&self, range: Range<usize>) -> impl Iterator<Item = &str> + '_ {
+        let Range { start, end } = range;
+        self.range_pieces(range).map(move |(offset, piece)| {
+            let text = self.piece_text(piece);
+            &text[start.saturating_sub(offset)..min(text.len(), end - offset)]
+        })
+    }
+
+    /// Iterates over the pieces overlapping the specified byte range of the document,
+    /// along with the document offset each starts at.
+    fn range_pieces(&self, range: Range<usize>) -> impl Iterator<Item = (usize, Piece)> + '_ {
+        let loc = self.locate(range.start);
+        self.pieces_from(loc.piece_index, range.start - loc.offset)
+            .take_while(move |(offset, _)| *offset < range.end)
+    }
+
+    /// Iterates over the byte offsets of every newline in the document.
+    fn newline_offsets(&self) -> impl Iterator<Item = usize> + '_ {
+        self.pieces_from(0, 0).flat_map(move |(start, piece)| {
+            self.piece_text(piece)
+                .match_indices('\n')
+                .map(move |(index, _)| start + index)
+        })
+    }
+
+    /// Iterates over the byte range of every line, excluding newlines.
+    fn line_spans(&self) -> impl Iterator<Item = Range<usize>> + '_ {
+        let len = self.len();
+        let mut start = 0;
+        self.newline_offsets()
+            .map(Some)
+            .chain(std::iter::once(None))
+            .map(move |newline| {
+                let end = newline.unwrap_or(len);
+                let span = start..end;
+                start = end + 1;
+                span
+            })
+    }
+
+    /// The byte range of the specified line, excluding its newline.
+    /// Lines are separated by '\n', so a document always has one more line than it has newlines.
+    fn line_span(&self, line: usize) -> Option<Range<usize>> {
+        let mut newlines = self.newline_offsets();
+        let start = match line {
+            0 => 0,
+            _ => newlines.nth(line - 1)? + 1,
+        };
+        let end = newlines.next().unwrap_or_else(|| self.len());
+        Some(start..end)
+    }
+
+    /// The byte range of the specified line, excluding its newline, or None if the line
+    /// doesn't exist. Lines are numbered from zero, and counted as for line_count.
+    pub fn line_range(&self, line: usize) -> Option<Range<usize>> {
+        self.line_span(line)
+    }
+
+    /// The byte range of the specified line including its newline, if it has one, so
+    /// that deleting the range removes the line entirely. Only the last line has no
+    /// newline. Returns None if the line doesn't exist.
+    pub fn line_range_with_newline(&self, line: usize) -> Option<Range<usize>> {
+        let span = self.line_span(line)?;
+        Some(span.start..min(span.end + 1, self.len()))
+    }
+
+    /// The number of lines in the document. As lines are separated by '\n', this
+    /// is one more than the number of newlines, so an empty document has one empty line
+    /// and a document ending in a newline has an empty last line.
+    pub fn line_count(&self) -> usize {
+        self.newline_offsets().count() + 1
+    }
+
+    /// The text of the specified line, without its newline, or None if the line doesn't
+    /// exist. Lines are numbered from zero, and counted as for line_count.
+    pub fn line(&self, line: usize) -> Option<String> {
+        self.line_span(line)
+            .map(|span| self.range_chunks(span).collect())
+    }
+
+    /// The number of lines in the document, as for line_count, but remembered
+    /// between calls so that it only needs to be counted again after the document
+    /// has been edited.
+    pub fn cached_line_count(&self) -> usize {
+        *self.line_count.get_or_init(|| self.line_count())
+    }
+
+    /// Writes the document to writer one piece at a time, without gathering it into a
+    /// single string first.
+    pub fn write_to<W: io::Write>(&self, writer: &mut W) -> io::Result<()> {
+        for piece in &self.pieces {
+            writer.write_all(self.piece_slice(*piece))?;
+        }
+        Ok(())
+    }
+
+    /// Iterates over the text slice of each piece in document order, without allocating.
+    pub fn chunks(&self) -> Chunks<'_> {
+        Chunks {
+            table: self,
+            pieces: self.pieces.iter(),
+        }
+    }
+
+    /// Iterates over the pieces in document order, yielding the index of each piece, the
+    /// document offset it starts at and its text, so that work done for a piece can be
+    /// cached and reused while that piece is unchanged.
+    pub fn enumerate_pieces(&self) -> impl Iterator<Item = (usize, usize, &str)> + '_ {
+        self.pieces_from(0, 0)
+            .enumerate()
+            .map(move |(index, (start, piece))| (index, start, self.piece_text(piece)))
+    }
+
+    /// Iterates over the text slices making up the specified line, excluding its newline,
+    /// without allocating. Returns None if the line doesn't exist.
+    pub fn line_chunks(&self, line: usize) -> Option<impl Iterator<Item = &str> + '_> {
+        self.line_span(line).map(|span| self.range_chunks(span))
+    }
+
+    /// Finds the byte offset of the first occurrence of needle that starts at or
+    /// after start. Matches may span any number of pieces.
+    /// An empty needle never matches.
+    fn search(&self, start: usize, needle: &str) -> Option<usize> {
+        if needle.is_empty() {
+            return None;
+        }
+        let mut bytes = self.bytes_from(start);
+        let mut position = start;
+        loop {
+            if bytes.clone().take(needle.len()).eq(needle.bytes()) {
+                return Some(position);
+            }
+            bytes.next()?;
+            position += 1;
+        }
+    }
+
+    /// Finds the byte offset of the last occurrence of needle, searching backwards from
+    /// the end of the document. An empty needle never matches.
+    fn rsearch(&self, needle: &str) -> Option<usize> {
+        if needle.is_empty() {
+            return None;
+        }
+        let mut bytes = self
+            .pieces
+            .iter()
+            .rev()
+            .flat_map(move |piece| self.piece_slice(*piece).iter().rev().copied());
+        let mut end = self.len();
+        loop {
+            if bytes.clone().take(needle.len()).eq(needle.bytes().rev()) {
+                return Some(end - needle.len());
+            }
+            bytes.next()?;
+            end -= 1;
+        }
+    }
+
+    /// Lazily iterates over the byte ranges of each non-overlapping occurrence
+    /// of needle in the document, from start to end.
+    /// Matches that span piece boundaries are found too. An empty needle
+    /// matches nothing.
+    pub fn match_ranges(&self, needle: &str) -> impl Iterator<Item = Range<usize>> + '_ {
+        let needle = needle.to_owned();
+        let mut position = 0;
+        std::iter::from_fn(move || {
+            let start = self.search(position, &needle)?;
+            position = start + needle.len();
+            Some(start..position)
+        })
+    }
+
+    /// Counts the non-overlapping occurrences of needle in the document, including those
+    /// spanning pieces, without gathering the document into a string.
+    /// An empty needle matches nothing.
+    pub fn count_matches(&self, needle: &str) -> usize {
+        self.match_ranges(needle).count()
+    }
+
+    /// Finds the byte offset of the first case-insensitive occurrence of needle.
+    ///
+    /// Matching uses full Unicode lowercase mappings rather than just ASCII, so
+    /// characters whose lowercase form has a different length (such as 'İ', which
+    /// lowercases to "i̇") are handled correctly.
+    /// A match always starts and ends on a character boundary of the document.
+    /// An empty needle never matches.
+    pub fn find_ignore_case(&self, needle: &str) -> Option<usize> {
+        let needle: Vec<char> = needle.chars().flat_map(char::to_lowercase).collect();
+        if needle.is_empty() {
+            return None;
+        }
+        let mut chars = self.char_indices_from(0);
+        loop {
+            let (start, _) = chars.clone().next()?;
+            if Self::starts_with_lowercase(chars.clone(), &needle) {
+                return Some(start);
+            }
+            chars.next();
+        }
+    }
+
+    /// Checks whether the lowercase form of chars begins with needle,
+    /// ending exactly at the end of a character's lowercase mapping.
+    fn starts_with_lowercase(chars: impl Iterator<Item = (usize, char)>, needle: &[char]) -> bool {
+        let mut remaining = needle;
+        for (_, c) in chars {
+            for lower in c.to_lowercase() {
+                match remaining.split_first() {
+                    Some((first, rest)) if *first == lower => remaining = rest,
+                    _ => return false,
+                }
+            }
+            if remaining.is_empty() {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Counts the whitespace characters in the document.
+    pub fn whitespace_count(&self) -> usize {
+        self.char_indices_from(0)
+            .filter(|(_, c)| c.is_whitespace())
+            .count()
+    }
+
+    /// Counts the words in the document, where a word is a maximal run of
+    /// non-whitespace characters. Words that span pieces are counted once.
+    pub fn word_count(&self) -> usize {
+        let mut in_word = false;
+        let mut count = 0;
+        for (_, c) in self.char_indices_from(0) {
+            if c.is_whitespace() {
+                in_word = false;
+            } else if !in_word {
+                in_word = true;
+                count += 1;
+            }
+        }
+        count
+    }
+
+    /// Appends a piece referencing the specified bytes of an existing buffer to the end
+    /// of the document, without attempting to merge it with the previous piece.
+    /// This lets tests and visualizers set up an exact layout of pieces.
+    /// Returns an error describing the problem if the buffer doesn't exist, or the
+    /// range is empty, out of bounds or not on character boundaries.
+    pub fn push_piece(
+        &mut self,
+        buffer_index: usize,
+        start: usize,
+        end: usize,
+    ) -> Result<(), String> {
+        self.check_piece(buffer_index, start, end)?;
+        if start == end {
+            return Err(format!("piece {}..{} is empty", start, end));
+        }
+        self.record_edit();
+        self.pieces.push(Piece {
+            buffer_index,
+            start,
+            end,
+        });
+        self.text_changed(self.len(), 0, end - start);
+        self.resized(end - start, 0);
+        self.edited();
+        Ok(())
+    }
+
+    /// Checks that a piece referencing the specified bytes of a buffer would be valid,
+    /// apart from being empty. Returns an error describing the problem if the buffer
+    /// doesn't exist, or the range is reversed, out of bounds or not on character
+    /// boundaries.
+    fn check_piece(&self, buffer_index: usize, start: usize, end: usize) -> Result<(), String> {
+        let buffer = self
+            .buffers
+            .get(buffer_index)
+            .ok_or_else(|| format!("buffer {} does not exist", buffer_index))?;
+        if start > end {
+            return Err(format!("piece {}..{} is reversed", start, end));
+        }
+        if end > buffer.len() {
+            return Err(format!(
+                "piece {}..{} is past the end of buffer {}",
+                start, end, buffer_index
+            ));
+        }
+        if !is_utf8_boundary(buffer, start) || !is_utf8_boundary(buffer, end) {
+            return Err(format!(
+                "piece {}..{} is not on character boundaries in buffer {}",
+                start, end, buffer_index
+            ));
+        }
+        Ok(())
+    }
+
+    /// The byte offset n characters after start, or the end of the document if there
+    /// are fewer characters than that. A start inside a character counts from the next
+    /// character boundary.
+    pub fn advance_chars(&self, start: usize, n: usize) -> usize {
+        let mut start = min(start, self.len());
+        while !self.is_char_boundary(start) {
+            start += 1;
+        }
+        self.char_indices_from(start)
+            .nth(n)
+            .map_or_else(|| self.len(), |(offset, _)| offset)
+    }
+
+    /// Converts a character index into the byte offset of that character.
+    /// Indices past the end of the document map to the document length.
+    fn char_to_byte(&self, char_index: usize) -> usize {
+        self.char_indices_from(0)
+            .nth(char_index)
+            .map_or_else(|| self.len(), |(offset, _)| offset)
+    }
+
+    /// Replaces the characters in the specified range of character indices with s.
+    /// The range is clamped to the end of the document.
+    pub fn replace_char_range(&mut self, chars: Range<usize>, s: &str) -> Result<(), EditError> {
+        let start = self.char_to_byte(chars.start);
+        let end = max(start, self.char_to_byte(chars.end));
+        self.replace(start, end - start, s)
+    }
+
+    /// Inserts s before the character at the specified character index.
+    /// Indices past the end of the document insert at the end.
+    pub fn insert_chars(&mut self, char_pos: usize, s: &str) -> Result<(), EditError> {
+        self.insert(self.char_to_byte(char_pos), s).map(|_| ())
+    }
+
+    /// Deletes char_len characters starting at the specified character index.
+    /// Like delete, if that would run past the end of the document, everything from the
+    /// index onwards is deleted, and indices past the end delete nothing.
+    pub fn delete_chars(&mut self, char_pos: usize, char_len: usize) -> Result<(), EditError> {
+        let start = self.char_to_byte(char_pos);
+        let end = self
+            .char_indices_from(start)
+            .nth(char_len)
+            .map_or_else(|| self.len(), |(offset, _)| offset);
+        self.delete(start, end - start)
+    }
+
+    /// The 0-based number of the line containing the specified byte offset.
+    /// Offsets past the end of the document are on the last line.
+    pub fn line_of_offset(&self, offset: usize) -> usize {
+        self.newline_offsets()
+            .take_while(|newline| *newline < offset)
+            .count()
+    }
+
+    /// Converts a byte offset into a 0-based line number and column, where the column is
+    /// the number of bytes from the start of the line, not the number of characters.
+    /// Offsets past the end of the document are clamped to the end.
+    pub fn position_to_line_col(&self, position: usize) -> (usize, usize) {
+        let position = min(position, self.len());
+        let (line, line_start) = self
+            .newline_offsets()
+            .take_while(|newline| *newline < position)
+            .fold((0, 0), |(line, _), newline| (line + 1, newline + 1));
+        (line, position - line_start)
+    }
+
+    /// Converts a 0-based line number and a column, counted in bytes from the start of the
+    /// line, into a byte offset. Lines past the last are clamped to the last line and
+    /// columns past the end of the line are clamped to its end, before its newline.
+    /// The column isn't checked against character boundaries.
+    pub fn line_col_to_position(&self, line: usize, column: usize) -> usize {
+        self.line_spans()
+            .take(line.saturating_add(1))
+            .last()
+            .map_or(0, |span| min(span.start.saturating_add(column), span.end))
+    }
+
+    /// Gets the text of the document, borrowing it from the buffer when the document
+    /// is held in a single piece and only allocating when it is fragmented.
+    pub fn to_cow(&self) -> Cow<'_, str> {
+        match self.pieces.as_slice() {
+            [] => Cow::Borrowed(""),
+            [piece] => Cow::Borrowed(self.piece_text(*piece)),
+            _ => Cow::Owned(self.to_string()),
+        }
+    }
+
+    /// Takes the table apart into its buffers and its pieces, each given as a
+    /// (buffer_index, start, end) triple of byte offsets into a buffer.
+    /// Panics if the table is binary, as its buffers may not be valid text.
+    pub fn into_parts(self) -> (Vec<String>, Vec<(usize, usize, usize)>) {
+        let pieces = self
+            .pieces
+            .iter()
+            .map(|piece| (piece.buffer_index, piece.start, piece.end))
+            .collect();
+        let buffers = self
+            .buffers
+            .into_iter()
+            .map(|buffer| Arc::try_unwrap(buffer).unwrap_or_else(|shared| (*shared).clone()))
+            .map(|buffer| String::from_utf8(buffer).expect("a binary table has no text parts"))
+            .collect();
+        (buffers, pieces)
+    }
+
+    /// Reassembles a table from buffers and piece triples, such as those returned by
+    /// into_parts. Empty pieces are skipped. The table starts with no undo history, as
+    /// a newly created table does. Returns an error if any of the pieces is invalid.
+    pub fn from_parts(
+        buffers: Vec<String>,
+        pieces: Vec<(usize, usize, usize)>,
+    ) -> Result<Self, String> {
+        let mut piece_table = PieceTable {
+            buffers: buffers
+                .into_iter()
+                .map(|buffer| Arc::new(buffer.into_bytes()))
+                .collect(),
+            ..Self::new()
+        };
+        for &(buffer_index, start, end) in &pieces {
+            piece_table.check_piece(buffer_index, start, end)?;
+        }
+        piece_table.pieces = pieces
+            .into_iter()
+            .filter(|(_, start, end)| start < end)
+            .map(|(buffer_index, start, end)| Piece {
+                buffer_index,
+                start,
+                end,
+            })
+            .collect();
+        Ok(piece_table)
+    }
+
+    /// Removes all leading and trailing characters that satisfy predicate, as a single
+    /// edit. Returns an error without changing the document if either end to be removed
+    /// overlaps a read-only region.
+    pub fn trim_matches<F: Fn(char) -> bool>(&mut self, predicate: F) -> Result<(), EditError> {
+        let end = self
+            .rev_char_indices_before(self.len())
+            .find(|(_, c)| !predicate(*c))
+            .map_or(0, |(offset, c)| offset + c.len_utf8());
+        let start = self
+            .char_indices_from(0)
+            .take_while(|(offset, _)| *offset < end)
+            .find(|(_, c)| !predicate(*c))
+            .map_or(end, |(offset, _)| offset);
+        let len = self.len() - end;
+        self.deletion_end(end, len)?;
+        self.deletion_end(0, start)?;
+        self.transaction(|table| {
+            table.delete(end, len)?;
+            table.delete(0, start)
+        })
+    }
+
+    /// Applies a set of hunks whose positions all refer to the document as it was before
+    /// any of them were applied. The hunks are applied from the highest start to the
+    /// lowest so that their positions stay valid. Hunks at the same start are inserted
+    /// in the order they are given, except that a hunk which removes text goes after any
+    /// pure insertions there, as they don't overlap it. All of the hunks are applied as a
+    /// single undo step.
+    /// Returns an error without changing the document if any hunks overlap, or if any
+    /// hunk would be rejected on its own.
+    pub fn apply_hunks(&mut self, hunks: &[Hunk]) -> Result<(), EditError> {
+        let mut sorted: Vec<&Hunk> = hunks.iter().collect();
+        sorted.sort_by_key(|hunk| (hunk.start, hunk.remove > 0));
+        for pair in sorted.windows(2) {
+            if pair[0].start.saturating_add(pair[0].remove) > pair[1].start {
+                return Err(EditError::Overlapping(pair[1].start));
+            }
+        }
+        let edits: Vec<Edit> = sorted
+            .iter()
+            .flat_map(|hunk| {
+                vec![
+                    Edit::Delete {
+                        pos: hunk.start,
+                        len: hunk.remove,
+                    },
+                    Edit::Insert {
+                        pos: hunk.start,
+                        text: hunk.insert.clone(),
+                    },
+                ]
+            })
+            .collect();
+        self.apply_edits(&edits)
+    }
+
+    /// Inserts several strings whose positions all refer to the document as it was before
+    /// any of them were inserted, and returns the byte offset in the final document at
+    /// which each string starts, in the order the edits were given.
+    /// The strings are inserted from the highest position to the lowest so that their
+    /// positions stay valid. Positions past the end are clamped to the end, and strings
+    /// at the same position end up in the order they are given. A string that cannot be
+    /// inserted is skipped, and its offset is where it would have started.
+    pub fn insert_batch(&mut self, edits: &[(usize, String)]) -> Vec<usize> {
+        let len = self.len();
+        let mut order: Vec<usize> = (0..edits.len()).collect();
+        order.sort_by_key(|&index| min(edits[index].0, len));
+        let mut added = vec![0; edits.len()];
+        for &index in order.iter().rev() {
+            let (position, s) = &edits[index];
+            let before = self.len();
+            if self.insert_clamped(*position, s).is_ok() {
+                added[index] = self.len() - before;
+            }
+        }
+        let mut offsets = vec![0; edits.len()];
+        let mut shift = 0;
+        for &index in &order {
+            offsets[index] = min(edits[index].0, len) + shift;
+            shift += added[index];
+        }
+        offsets
+    }
+
+    /// Finds the first piece that starts or ends part way through a UTF-8 character.
+    /// Returns the index of the piece and the offending byte offset within its buffer,
+    /// or None if every piece lies on character boundaries.
+    pub fn first_boundary_violation(&self) -> Option<(usize, usize)> {
+        self.pieces.iter().enumerate().find_map(|(index, piece)| {
+            let buffer = self.buffers.get(piece.buffer_index)?;
+            [piece.start, piece.end]
+                .iter()
+                .find(|offset| !is_utf8_boundary(buffer, **offset))
+                .map(|offset| (index, *offset))
+        })
+    }
+
+    /// Checks that every piece refers to a non-empty range of an existing buffer that
+    /// starts and ends on character boundaries, returning a message naming the first
+    /// piece that doesn't.
+    pub fn validate(&self) -> Result<(), String> {
+        for (index, piece) in self.pieces.iter().enumerate() {
+            let buffer = self.buffers.get(piece.buffer_index).ok_or_else(|| {
+                format!(
+                    "piece {} refers to buffer {}, but there are only {} buffers",
+                    index,
+                    piece.buffer_index,
+                    self.buffers.len()
+                )
+            })?;
+            if piece.start >= piece.end {
+                return Err(format!(
+                    "piece {} is empty or reversed: {}..{}",
+                    index, piece.start, piece.end
+                ));
+            }
+            if piece.end > buffer.len() {
+                return Err(format!(
+                    "piece {} ends at {}, past the end of buffer {} at {}",
+                    index,
+                    piece.end,
+                    piece.buffer_index,
+                    buffer.len()
+                ));
+            }
+            if self.binary {
+                continue;
+            }
+            if let Some(offset) = [piece.start, piece.end]
+                .iter()
+                .find(|offset| !is_utf8_boundary(buffer, **offset))
+            {
+                return Err(format!(
+                    "piece {} splits a character at offset {} of buffer {}",
+                    index, offset, piece.buffer_index
+                ));
+            }
+            if std::str::from_utf8(&buffer[piece.start..piece.end]).is_err() {
+                return Err(format!("piece {} isn't valid UTF-8", index));
+            }
+        }
+        Ok(())
+    }
+
+    /// Deletes every non-overlapping occurrence of needle, including those spanning
+    /// pieces, and returns the number of occurrences removed.
+    /// Occurrences overlapping read-only regions are left in place.
+    pub fn delete_all(&mut self, needle: &str) -> usize {
+        let matches: Vec<_> = self.match_ranges(needle).collect();
+        matches
+            .iter()
+            .rev()
+            .filter(|range| self.delete(range.start, range.len()).is_ok())
+            .count()
+    }
+
+    /// Replaces every non-overlapping occurrence of needle, including those spanning
+    /// pieces, with replacement, and returns the number of occurrences replaced.
+    /// All of the occurrences are found before any are replaced, so occurrences of needle
+    /// within the replacement text are never replaced themselves.
+    /// Occurrences overlapping read-only regions are left in place.
+    pub fn replace_all(&mut self, needle: &str, replacement: &str) -> usize {
+        let matches: Vec<_> = self.match_ranges(needle).collect();
+        matches
+            .iter()
+            .rev()
+            .filter(|range| self.replace(range.start, range.len(), replacement).is_ok())
+            .count()
+    }
+
+    /// Iterates over the text of the document split at both piece boundaries and
+    /// style boundaries, so that every chunk has a single style. Chunks outside all
+    /// of the style ranges have no style.
+    /// The style ranges are byte ranges of the document, and must be sorted and not overlap.
+    /// A style range with an end inside a character is widened to cover the character,
+    /// as for clamp_range, and empty style ranges produce no chunks.
+    pub fn styled_chunks<'a, Style>(
+        &'a self,
+        styles: &'a [(Range<usize>, Style)],
+    ) -> impl Iterator<Item = (&'a str, Option<&'a Style>)> {
+        self.pieces_from(0, 0).flat_map(move |(start, piece)| {
+            let text = self.piece_text(piece);
+            let end = start + piece.len();
+            let mut chunks = Vec::new();
+            let mut cursor = 0;
+            for (range, style) in styles
+                .iter()
+                .filter(|(range, _)| range.start < end && start < range.end)
+            {
+                let mut style_start = max(range.start.saturating_sub(start), cursor);
+                while !text.is_char_boundary(style_start) {
+                    style_start -= 1;
+                }
+                let mut style_end = max(min(range.end, end) - start, style_start);
+                while !text.is_char_boundary(style_end) {
+                    style_end += 1;
+                }
+                if style_start < style_end {
+                    if style_start > cursor {
+                        chunks.push((&text[cursor..style_start], None));
+                    }
+                    chunks.push((&text[style_start..style_end], Some(style)));
+                    cursor = style_end;
+                }
+            }
+            if cursor < text.len() {
+                chunks.push((&text[cursor..], None));
+            }
+            chunks
+        })
+    }
+
+    /// The buffer index and byte range within that buffer referenced by the piece
+    /// at index, or None if there is no such piece.
+    pub fn piece_origin(&self, index: usize) -> Option<(usize, Range<usize>)> {
+        self.pieces
+            .get(index)
+            .map(|piece| (piece.buffer_index, piece.start..piece.end))
+    }
+
+    /// The bytes referenced by the piece at index, or None if there is no such piece.
+    pub fn piece_bytes(&self, index: usize) -> Option<&[u8]> {
+        self.pieces.get(index).map(|piece| self.piece_slice(*piece))
+    }
+
+    /// Iterates over the characters within the specified byte range of the document,
+    /// along with their byte offsets from the start of the document. Only the pieces
+    /// overlapping the range are visited.
+    pub fn char_indices_in_range(
+        &self,
+        range: Range<usize>,
+    ) -> impl Iterator<Item = (usize, char)> + '_ {
+        let range = self.clamp_range(range);
+        self.char_indices_from(range.start)
+            .take_while(move |(offset, _)| *offset < range.end)
+    }
+
+    /// The byte ranges of the document that don't come from the original text the table
+    /// was created with, such as text that has been inserted since. Adjacent changed
+    /// pieces are reported as a single range. Text appended into spare capacity of the
+    /// original buffer counts as changed.
+    /// Compacting the table forgets which text was original, so afterwards the whole
+    /// document is reported as changed.
+    pub fn change_regions(&self) -> Vec<Range<usize>> {
+        let mut regions: Vec<Range<usize>> = Vec::new();
+        for (start, piece) in self.pieces_from(0, 0) {
+            let original_len = match self.original_len {
+                Some(len) if piece.buffer_index == 0 => len,
+                _ => 0,
+            };
+            let end = start + piece.len();
+            let start = start + min(piece.len(), original_len.saturating_sub(piece.start));
+            if start == end {
+                continue;
+            }
+            match regions.last_mut() {
+                Some(region) if region.end == start => region.end = end,
+                _ => regions.push(start..end),
+            }
+        }
+        regions
+    }
+
+    /// The byte offset of the start of the line containing the point the specified
+    /// fraction of the way through the document, for jumping to a scrollbar position.
+    /// Fractions are clamped to between 0.0 and 1.0.
+    pub fn offset_at_fraction(&self, fraction: f64) -> usize {
+        let target = (fraction.clamp(0.0, 1.0) * self.len() as f64) as usize;
+        self.line_span(self.line_of_offset(target))
+            .map_or(0, |span| span.start)
+    }
+
+    /// Appends the document held by other to the end of this one.
+    /// Other's buffers are moved into this table and its pieces are appended with their
+    /// buffer indices remapped, so no text is copied. Buffers the tables already share
+    /// are only held once, so the pieces either side of the join merge if they are
+    /// contiguous, as they are when rejoining a table that was split off.
+    pub fn append_table(&mut self, other: PieceTable) {
+        if other.is_empty() {
+            return;
+        }
+        self.binary |= other.binary;
+        self.record_edit();
+        self.text_changed(self.len(), 0, other.len());
+        self.resized(other.len(), 0);
+        let indices = self.adopt_buffers(other.buffers);
+        let seam = self.pieces.len();
+        self.pieces
+            .extend(other.pieces.into_iter().map(|piece| Piece {
+                buffer_index: indices[piece.buffer_index],
+                ..piece
+            }));
+        self.merge_at(seam);
+        self.edited();
+    }
+
+    /// Adds buffers from another table to this one, returning the index each ends up at.
+    /// Buffers this table already shares are reused rather than added again.
+    fn adopt_buffers(&mut self, buffers: impl IntoIterator<Item = Buffer>) -> Vec<usize> {
+        let shared = self.buffers.len();
+        buffers
+            .into_iter()
+            .map(|buffer| {
+                self.buffers[..shared]
+                    .iter()
+                    .position(|existing| Arc::ptr_eq(existing, &buffer))
+                    .unwrap_or_else(|| {
+                        self.buffers.push(buffer);
+                        self.buffers.len() - 1
+                    })
+            })
+            .collect()
+    }
+
+    /// Replaces range with the document held by other, as a single edit.
+    /// Other's buffers are shared with this table and its pieces are spliced in with their
+    /// buffer indices remapped, so its text is never copied.
+    /// Returns an error without changing the document if range can't be deleted.
+    pub fn splice_table(
+        &mut self,
+        range: Range<usize>,
+        other: &PieceTable,
+    ) -> Result<(), EditError> {
+        let Range { start, end } = range;
+        let end = self.deletion_end(start, end.saturating_sub(start))?;
+        if start == end && other.is_empty() {
+            return Ok(());
+        }
+        self.binary |= other.binary;
+        self.record_edit();
+        self.splice_pieces(
+            start,
+            end - start,
+            &other.buffers,
+            other.pieces.iter().copied(),
+        );
+        Ok(())
+    }
+
+    /// Inserts the text in the specified byte range of src at position, sharing src's
+    /// buffers rather than copying the text. The range is clamped to src as for
+    /// clamp_range. To copy text within a document, take a snapshot of it and insert from
+    /// that, as the snapshot shares the document's buffers.
+    /// Returns an error without changing the document in the same cases as insert.
+    pub fn insert_pieces_from(
+        &mut self,
+        position: usize,
+        src: &PieceTable,
+        range: Range<usize>,
+    ) -> Result<(), EditError> {
+        if position > self.len() {
+            return Err(EditError::OutOfBounds(position));
+        }
+        self.check_insert(position)?;
+        let range = src.clamp_range(range);
+        if range.is_empty() {
+            return Ok(());
+        }
+        self.binary |= src.binary;
+        self.record_edit();
+        let pieces = src
+            .range_pieces(range.clone())
+            .map(|(offset, piece)| Piece {
+                buffer_index: piece.buffer_index,
+                start: piece.start + range.start.saturating_sub(offset),
+                end: piece.start + min(piece.len(), range.end - offset),
+            });
+        self.splice_pieces(position, 0, &src.buffers, pieces);
+        Ok(())
+    }
+
+    /// Replaces removed bytes starting at position with pieces referencing another table's
+    /// buffers, adopting the buffers they reference. The caller checks the removal and
+    /// records the edit for undo.
+    fn splice_pieces(
+        &mut self,
+        position: usize,
+        removed: usize,
+        buffers: &[Buffer],
+        pieces: impl Iterator<Item = Piece>,
+    ) {
+        let mut indices = vec![None; buffers.len()];
+        let pieces: Vec<Piece> = pieces
+            .map(|piece| {
+                let adopted = *indices[piece.buffer_index].get_or_insert_with(|| {
+                    self.adopt_buffers(std::iter::once(buffers[piece.buffer_index].clone()))[0]
+                });
+                Piece {
+                    buffer_index: adopted,
+                    ..piece
+                }
+            })
+            .collect();
+        let len = pieces.iter().map(Piece::len).sum();
+        self.text_changed(position, removed, len);
+        let index = self.split(self.locate(position), 0);
+        self.remove_bytes(index, removed);
+        self.pieces.splice(index..index, pieces);
+        self.resized(len, removed);
+        for region in &mut self.readonly_regions {
+            if position + removed <= region.start {
+                region.start = region.start - removed + len;
+                region.end = region.end - removed + len;
+            }
+        }
+        self.edited();
+    }
+
+    /// Finds the byte offset of the first occurrence of needle in the document.
+    /// Matches may span any number of pieces. An empty needle never matches.
+    pub fn find(&self, needle: &str) -> Option<usize> {
+        self.search(0, needle)
+    }
+
+    /// Finds the byte offset of the first occurrence of needle starting at or after start.
+    /// Occurrences that begin before start aren't matched, even if they extend past it.
+    pub fn find_from(&self, start: usize, needle: &str) -> Option<usize> {
+        self.search(start, needle)
+    }
+
+    /// Iterates over the text of every line, without the newlines, counting lines as for
+    /// to_lines. A line held within a single piece is borrowed from its buffer, and only
+    /// a line spanning pieces is gathered into a new string.
+    pub fn lines(&self) -> impl Iterator<Item = Cow<'_, str>> + '_ {
+        self.line_spans().map(move |span| {
+            let mut chunks = self.range_chunks(span);
+            match (chunks.next(), chunks.next()) {
+                (None, _) => Cow::Borrowed(""),
+                (Some(only), None) => Cow::Borrowed(only),
+                (Some(first), Some(second)) => {
+                    let mut line = String::from(first);
+                    line.push_str(second);
+                    line.extend(chunks);
+                    Cow::Owned(line)
+                }
+            }
+        })
+    }
+
+    /// Collects the text of every line into a vector, without the newlines.
+    /// Unlike str::lines, a document ending in a newline has an empty last line,
+    /// so that there is an entry for every line number, and '\r' is left in place.
+    pub fn to_lines(&self) -> Vec<String> {
+        self.lines().map(Cow::into_owned).collect()
+    }
+
+    /// Clamps a byte range to the document, widening it as needed so that both ends
+    /// lie on character boundaries. An inverted range becomes an empty range at its start.
+    pub fn clamp_range(&self, range: Range<usize>) -> Range<usize> {
+        let len = self.len();
+        let mut start = min(range.start, len);
+        let mut end = min(max(range.start, range.end), len);
+        while !self.is_char_boundary(start) {
+            start -= 1;
+        }
+        while !self.is_char_boundary(end) {
+            end += 1;
+        }
+        start..end
+    }
+
+    /// Gets the text in the specified byte range of the document, visiting only the pieces
+    /// overlapping it. The range is clamped to the document as for clamp_range.
+    pub fn substring(&self, range: Range<usize>) -> String {
+        let range = self.clamp_range(range);
+        let mut text = String::with_capacity(range.len());
+        text.extend(self.range_chunks(range));
+        text
+    }
+
+    /// Counts the bytes of the document that satisfy f.
+    pub fn count_bytes<F: Fn(u8) -> bool>(&self, f: F) -> usize {
+        self.pieces
+            .iter()
+            .map(|piece| {
+                self.piece_slice(*piece)
+                    .iter()
+                    .filter(|byte| f(**byte))
+                    .count()
+            })
+            .sum()
+    }
+
+    /// Counts the characters of the document that satisfy f.
+    pub fn count_chars<F: Fn(char) -> bool>(&self, f: F) -> usize {
+        self.pieces
+            .iter()
+            .map(|piece| self.piece_text(*piece).chars().filter(|c| f(*c)).count())
+            .sum()
+    }
+
+    /// Splits the document in two at byte offset at, leaving the text before it in this
+    /// table and returning a new table holding the rest.
+    /// Both tables share the buffers, so only the pieces are divided between them, and
+    /// the buffers are kept alive for as long as either table is.
+    /// An offset past the end returns an empty table, and an offset inside a character
+    /// splits at the end of that character. Read-only regions are divided between the
+    /// tables rather than preventing the split.
+    pub fn split_off(&mut self, at: usize) -> PieceTable {
+        let mut at = min(at, self.len());
+        while !self.is_char_boundary(at) {
+            at += 1;
+        }
+        let removed = self.len() - at;
+        if removed > 0 {
+            self.record_edit();
+            self.text_changed(at, removed, 0);
+        }
+        let index = self.split(self.locate(at), 0);
+        self.len = OnceLock::from(at);
+        let mut tail = PieceTable {
+            buffers: self.buffers.clone(),
+            pieces: self.pieces.split_off(index),
+            max_pieces: self.max_pieces,
+            normalize_eol: self.normalize_eol,
+            binary: self.binary,
+            original_len: self.original_len,
+            history_limit: self.history_limit,
+            buffer_growth: self.buffer_growth,
+            ..Self::new()
+        };
+        for region in &mut self.readonly_regions {
+            if region.end > at {
+                tail.readonly_regions
+                    .push(max(region.start, at) - at..region.end - at);
+                region.end = max(region.start, at);
+            }
+        }
+        self.readonly_regions.retain(|region| !region.is_empty());
+        if removed > 0 {
+            self.edited();
+        }
+        tail
+    }
+
+    /// Splits the document in two before the character at char_index, leaving the text
+    /// before it in this table and returning a new table holding the rest.
+    /// Splitting past the last character returns an empty table.
+    pub fn split_off_char(&mut self, char_index: usize) -> PieceTable {
+        let at = self.char_to_byte(char_index);
+        self.split_off(at)
+    }
+
+    /// Works out a list of edits that turns this document into other's, using Myers' diff
+    /// algorithm over their characters so that every edit lies on character boundaries.
+    /// Every position refers to this document as it is, and the edits are listed from
+    /// the end of the document to the start, so applying them one at a time in order
+    /// gives other's text. Where text is replaced, the delete comes before the insert.
+    /// The time and memory taken grow with the size of the differing text times the
+    /// number of characters that differ, after any text common to the start and end of
+    /// both documents is skipped.
+    pub fn diff(&self, other: &PieceTable) -> Vec<Edit> {
+        let a: Vec<char> = self.chars().collect();
+        let b: Vec<char> = other.chars().collect();
+        let prefix = a.iter().zip(&b).take_while(|(x, y)| x == y).count();
+        let suffix = a[prefix..]
+            .iter()
+            .rev()
+            .zip(b[prefix..].iter().rev())
+            .take_while(|(x, y)| x == y)
+            .count();
+        let mut pos: usize = a[..prefix].iter().map(|c| c.len_utf8()).sum();
+        // Each run of differences becomes a (position, deleted bytes, inserted text) triple.
+        let mut runs = Vec::new();
+        let mut run: Option<(usize, usize, String)> = None;
+        for op in shortest_edit(&a[prefix..a.len() - suffix], &b[prefix..b.len() - suffix]) {
+            match op {
+                DiffOp::Keep(c) => {
+                    runs.extend(run.take());
+                    pos += c.len_utf8();
+                }
+                DiffOp::Delete(c) => {
+                    run.get_or_insert_with(|| (pos, 0, String::new())).1 += c.len_utf8();
+                    pos += c.len_utf8();
+                }
+                DiffOp::Insert(c) => run.get_or_insert_with(|| (pos, 0, String::new())).2.push(c),
+            }
+        }
+        runs.extend(run);
+        let mut edits = Vec::new();
+        for (pos, len, text) in runs.into_iter().rev() {
+            if len > 0 {
+                edits.push(Edit::Delete { pos, len });
+            }
+            if !text.is_empty() {
+                edits.push(Edit::Insert { pos, text });
+            }
+        }
+        edits
+    }
+
+    /// Applies a batch of edits whose positions all refer to the document as it was before
+    /// any of them were applied, as a single undo step.
+    /// The edits are applied from the highest position to the lowest so that their
+    /// positions stay valid. At the same position deletes are applied before inserts, and
+    /// inserted strings end up in the order they are given. The edits diff returns can be
+    /// applied this way too.
+    /// Returns an error without changing the document if any edit would be rejected on
+    /// its own, or if deletes overlap each other or an insert falls inside a delete.
+    pub fn apply_edits(&mut self, edits: &[Edit]) -> Result<(), EditError> {
+        let mut sorted: Vec<&Edit> = edits.iter().collect();
+        sorted.sort_by_key(|edit| edit.pos());
+        let mut deleted = 0..0;
+        for edit in &sorted {
+            let end = match edit {
+                Edit::Insert { pos, .. } if *pos > self.len() => {
+                    return Err(EditError::OutOfBounds(*pos));
+                }
+                Edit::Insert { pos, .. } => self.check_insert(*pos).map(|_| *pos)?,
+                Edit::Delete { pos, len } => self.deletion_end(*pos, *len)?,
+            };
+            let pos = edit.pos();
+            if pos < deleted.end && deleted.start < end {
+                return Err(EditError::Overlapping(pos));
+            }
+            if let Edit::Delete { .. } = edit {
+                deleted = pos..end;
+            }
+        }
+        self.transaction(|table| {
+            for group in sorted.chunk_by(|a, b| a.pos() == b.pos()).rev() {
+                let (deletes, inserts): (Vec<&Edit>, Vec<&Edit>) = group
+                    .iter()
+                    .partition(|edit| matches!(edit, Edit::Delete { .. }));
+                for edit in deletes.into_iter().chain(inserts.into_iter().rev()) {
+                    table.apply_edit(edit)?;
+                }
+            }
+            Ok(())
+        })
+    }
+
+    /// Applies a single edit to the document.
+    fn apply_edit(&mut self, edit: &Edit) -> Result<(), EditError> {
+        match edit {
+            Edit::Insert { pos, text } => self.insert(*pos, text).map(|_| ()),
+            Edit::Delete { pos, len } => self.delete(*pos, *len),
+        }
+    }
+
+    /// Applies edit only if the document is still at the expected version, so that an
+    /// edit computed against an older version of the document is rejected.
+    /// Returns the new version if the edit was applied, or the current version if not,
+    /// either because the version didn't match or because the edit itself was rejected.
+    pub fn apply_if_version(&mut self, expected: u64, edit: &Edit) -> Result<u64, u64> {
+        if self.version != expected {
+            return Err(self.version);
+        }
+        self.apply_edit(edit).map_err(|_| self.version)?;
+        Ok(self.version)
+    }
+
+    /// Removes prefix from the start of the document if the document starts with it.
+    /// Returns whether the prefix was removed.
+    pub fn strip_prefix(&mut self, prefix: &str) -> bool {
+        self.starts_with(prefix) && self.delete(0, prefix.len()).is_ok()
+    }
+
+    /// Removes suffix from the end of the document if the document ends with it.
+    /// Returns whether the suffix was removed.
+    pub fn strip_suffix(&mut self, suffix: &str) -> bool {
+        let start = match self.len().checked_sub(suffix.len()) {
+            Some(start) => start,
+            None => return false,
+        };
+        self.bytes_from(start).eq(suffix.bytes()) && self.delete(start, suffix.len()).is_ok()
+    }
+
+    /// The distinct indices of the buffers referenced by the pieces making up the
+    /// specified line, in the order they appear. Empty or missing lines touch no buffers.
+    pub fn buffers_touched_by_line(&self, line: usize) -> Vec<usize> {
+        let mut buffers = Vec::new();
+        if let Some(span) = self.line_span(line) {
+            for (_, piece) in self.range_pieces(span) {
+                if !buffers.contains(&piece.buffer_index) {
+                    buffers.push(piece.buffer_index);
+                }
+            }
+        }
+        buffers
+    }
+
+    /// Creates a cursor at the start of the document that can walk through it and edit
+    /// it as it goes.
+    pub fn edit_cursor(&mut self) -> EditCursor<'_> {
+        EditCursor {
+            table: self,
+            offset: 0,
+        }
+    }
+
+    /// Creates a cursor at the specified byte position that can step through the document
+    /// without locating its position again for each step. A position past the end is
+    /// clamped to the end, and a position inside a character moves to the end of it.
+    pub fn cursor_at(&self, position: usize) -> Cursor<'_> {
+        let mut position = min(position, self.len());
+        while !self.is_char_boundary(position) {
+            position += 1;
+        }
+        Cursor {
+            table: self,
+            loc: self.locate(position),
+            position,
+        }
+    }
+
+    /// Finds the word containing the character at the specified byte position, where a
+    /// word is a run of alphanumeric characters and underscores, returning its byte range
+    /// and its text. Returns None if the character at the position isn't part of a word,
+    /// or the position is at the end of the document or isn't on a character boundary.
+    pub fn word_at(&self, position: usize) -> Option<(Range<usize>, String)> {
+        let is_word = |c: char| c.is_alphanumeric() || c == '_';
+        self.char_at(position).filter(|c| is_word(*c))?;
+        let mut cursor = self.cursor_at(position);
+        while let Some(c) = cursor.prev_char() {
+            if !is_word(c) {
+                cursor.next_char();
+                break;
+            }
+        }
+        let start = cursor.position();
+        let mut word = String::new();
+        while let Some(c) = cursor.next_char().filter(|c| is_word(*c)) {
+            word.push(c);
+        }
+        Some((start..start + word.len(), word))
+    }
+
+    /// Iterates over runs of consecutive pieces that come from the same buffer, yielding
+    /// the buffer index and the byte range of the document the run covers.
+    pub fn origin_runs(&self) -> impl Iterator<Item = (usize, Range<usize>)> + '_ {
+        let mut pieces = self.pieces_from(0, 0).peekable();
+        std::iter::from_fn(move || {
+            let (start, piece) = pieces.next()?;
+            let mut end = start + piece.len();
+            while let Some((next_start, next)) =
+                pieces.next_if(|(_, next)| next.buffer_index == piece.buffer_index)
+            {
+                end = next_start + next.len();
+            }
+            Some((piece.buffer_index, start..end))
+        })
+    }
+
+    /// Shortens the document to at most max_chars characters, removing everything after
+    /// them. Does nothing if the document is already no longer than that.
+    /// Returns an error if the text to be removed overlaps a read-only region.
+    pub fn truncate_chars(&mut self, max_chars: usize) -> Result<(), EditError> {
+        let end = self.char_to_byte(max_chars);
+        let len = self.len();
+        self.delete(end, len - end)
+    }
+
+    /// For each pair of neighbouring pieces, whether the first could merge with the second
+    /// because they reference contiguous bytes of the same buffer. Edits merge pieces
+    /// where they can, so these show where the table holds more pieces than it needs.
+    pub fn merge_opportunities(&self) -> Vec<bool> {
+        self.pieces
+            .windows(2)
+            .map(|pair| {
+                let mut first = pair[0];
+                first.merge(pair[1])
+            })
+            .collect()
+    }
+
+    /// Fuses every pair of neighbouring pieces that reference contiguous bytes of the same
+    /// buffer, without copying any text. This is a cheaper alternative to compact that
+    /// keeps the buffers and the undo history.
+    pub fn coalesce(&mut self) {
+        let before = self.pieces.len();
+        self.pieces.dedup_by(|next, previous| previous.merge(*next));
+        if self.pieces.len() < before {
+            self.piece_ends.take();
+        }
+    }
+
+    /// Describes every piece in document order, for tools that show how the table is laid
+    /// out.
+    pub fn pieces_debug(&self) -> Vec<PieceInfo<'_>> {
+        self.pieces_from(0, 0)
+            .map(|(start, piece)| PieceInfo {
+                buffer_index: piece.buffer_index,
+                buffer_range: piece.start..piece.end,
+                start,
+                text: self.piece_text(piece),
+            })
+            .collect()
+    }
+
+    /// Describes the layout of the table for people learning how it works or reporting
+    /// bugs, listing each piece in document order as [buffer: "text"], followed by the
+    /// contents of each buffer.
+    pub fn debug_layout(&self) -> String {
+        let mut layout = String::from("pieces:\n");
+        for piece in &self.pieces {
+            let text = String::from_utf8_lossy(self.piece_slice(*piece));
+            layout += &format!("[{}: {:?}]\n", piece.buffer_index, text);
+        }
+        layout += "buffers:\n";
+        for (index, buffer) in self.buffers.iter().enumerate() {
+            layout += &format!("{}: {:?}\n", index, String::from_utf8_lossy(buffer));
+        }
+        layout
+    }
+
+    /// For each buffer, the number of pieces referencing it.
+    pub fn pieces_per_buffer(&self) -> Vec<usize> {
+        let mut counts = vec![0; self.buffers.len()];
+        for piece in &self.pieces {
+            counts[piece.buffer_index] += 1;
+        }
+        counts
+    }
+
+    /// The number of pieces the document is split into.
+    pub fn piece_count(&self) -> usize {
+        self.pieces.len()
+    }
+
+    /// The number of buffers the table holds, including any no longer referenced.
+    pub fn buffer_count(&self) -> usize {
+        self.buffers.len()
+    }
+
+    /// The total bytes of text held in the buffers, including text that has been deleted
+    /// from the document. Compacting the table brings this down to len.
+    pub fn buffer_bytes(&self) -> usize {
+        self.buffers.iter().map(|buffer| buffer.len()).sum()
+    }
+
+    /// The total bytes allocated for the buffers, including spare capacity that hasn't
+    /// been filled with text yet, so always at least buffer_bytes.
+    pub fn buffer_capacity(&self) -> usize {
+        self.buffers.iter().map(|buffer| buffer.capacity()).sum()
+    }
+
+    /// The byte offset of the start of the word touching position, where words are
+    /// runs of non-whitespace characters. If the character before position is
+    /// whitespace, position itself is returned.
+    pub fn word_start(&self, position: usize) -> usize {
+        let position = min(position, self.len());
+        self.rev_char_indices_before(position)
+            .take_while(|(_, c)| !c.is_whitespace())
+            .last()
+            .map_or(position, |(offset, _)| offset)
+    }
+
+    /// The byte offset of the end of the word touching position, where words are
+    /// runs of non-whitespace characters. If the character after position is
+    /// whitespace, position itself is returned.
+    pub fn word_end(&self, position: usize) -> usize {
+        let position = min(position, self.len());
+        self.char_indices_from(position)
+            .take_while(|(_, c)| !c.is_whitespace())
+            .last()
+            .map_or(position, |(offset, c)| offset + c.len_utf8())
+    }
+
+    /// Creates a table from the chunks of a rope, such as those yielded by iterating
+    /// over a ropey::Rope's chunks, holding them as its original text.
+    #[cfg(feature = "rope")]
+    pub fn from_rope_chunks<I: IntoIterator<Item = String>>(chunks: I) -> Self {
+        Self::from_string(chunks.into_iter().collect())
+    }
+
+    /// Iterates over the document as a sequence of text chunks suitable for feeding a
+    /// rope builder, such as ropey::RopeBuilder::append.
+    #[cfg(feature = "rope")]
+    pub fn rope_chunks(&self) -> impl Iterator<Item = &str> + '_ {
+        self.chunks()
+    }
+
+    /// Removes all of the text, leaving an empty document. The buffers are kept so that
+    /// the clear can be undone, until the table is compacted.
+    /// Returns an error without changing the document if any of it is read-only.
+    pub fn clear(&mut self) -> Result<(), EditError> {
+        if let Some(region) = self
+            .readonly_regions
+            .iter()
+            .find(|region| !region.is_empty())
+        {
+            return Err(EditError::ReadOnly(region.start));
+        }
+        if self.pieces.is_empty() {
+            return Ok(());
+        }
+        self.record_edit();
+        self.text_changed(0, self.len(), 0);
+        self.pieces.clear();
+        self.readonly_regions.clear();
+        self.len = OnceLock::from(0);
+        self.edited();
+        Ok(())
+    }
+
+    /// Removes everything from byte offset len to the end of the document. Does nothing
+    /// if len is past the end.
+    /// Returns an error without changing the document if len isn't on a character
+    /// boundary or the tail overlaps a read-only region.
+    pub fn truncate(&mut self, len: usize) -> Result<(), EditError> {
+        let end = self.len();
+        if len >= end {
+            return Ok(());
+        }
+        self.delete(len, end - len)
+    }
+
+    /// Removes everything from byte offset len to the end of the document and returns
+    /// the removed text. Does nothing and returns an empty string if len is past the end.
+    /// Returns an error without changing the document if len isn't on a character
+    /// boundary or the tail overlaps a read-only region.
+    pub fn truncate_take(&mut self, len: usize) -> Result<String, EditError> {
+        let end = self.len();
+        if len >= end {
+            return Ok(String::new());
+        }
+        let tail = self.range_chunks(len..end).collect();
+        self.delete(len, end - len)?;
+        Ok(tail)
+    }
+
+    /// Replaces the first occurrence of from with to.
+    /// Returns whether an occurrence was found and replaced.
+    pub fn replace_first(&mut self, from: &str, to: &str) -> bool {
+        match self.search(0, from) {
+            Some(position) => self.replace(position, from.len(), to).is_ok(),
+            None => false,
+        }
+    }
+
+    /// Replaces the last occurrence of from with to.
+    /// Returns whether an occurrence was found and replaced.
+    pub fn replace_last(&mut self, from: &str, to: &str) -> bool {
+        match self.rsearch(from) {
+            Some(position) => self.replace(position, from.len(), to).is_ok(),
+            None => false,
+        }
+    }
+
+    /// The number of characters in the specified line, excluding its newline,
+    /// or None if the line doesn't exist.
+    pub fn line_char_len(&self, line: usize) -> Option<usize> {
+        let span = self.line_span(line)?;
+        Some(
+            self.range_chunks(span)
+                .map(|chunk| chunk.chars().count())
+                .sum(),
+        )
+    }
+
+    /// Checks whether the specified line has no text, not counting its newline,
+    /// or returns None if the line doesn't exist.
+    pub fn is_line_empty(&self, line: usize) -> Option<bool> {
+        self.line_span(line).map(|span| span.is_empty())
+    }
+
+    /// Inserts text as a new line after the specified line, adding a newline to the end
+    /// of text if it doesn't already have one.
+    /// Inserting after the last line, or any line past it, appends text to the document,
+    /// first adding a newline if the document doesn't already end with one.
+    pub fn insert_line_after(&mut self, line: usize, text: &str) -> Result<(), EditError> {
+        let mut line_text = String::with_capacity(text.len() + 2);
+        let position = match line.checked_add(1).and_then(|next| self.line_span(next)) {
+            Some(span) => span.start,
+            None => {
+                let last = self.rev_char_indices_before(self.len()).next();
+                if !matches!(last, None | Some((_, '\n'))) {
+                    line_text.push('\n');
+                }
+                self.len()
+            }
+        };
+        line_text.push_str(text);
+        if !text.ends_with('\n') {
+            line_text.push('\n');
+        }
+        self.insert(position, line_text).map(|_| ())
+    }
+
+    /// Replaces every tab with the spaces needed to reach the next tab stop, with a stop
+    /// every tab_width columns, where each character takes up one column. A tab_width
+    /// of zero removes tabs. All of the tabs are replaced as a single undo step.
+    /// Returns an error without changing the document if any tab is in a read-only region.
+    pub fn expand_tabs(&mut self, tab_width: usize) -> Result<(), EditError> {
+        let mut tabs = Vec::new();
+        let mut column = 0;
+        for (offset, c) in self.char_indices() {
+            match c {
+                '\n' => column = 0,
+                '\t' => {
+                    let spaces = match tab_width {
+                        0 => 0,
+                        _ => tab_width - column % tab_width,
+                    };
+                    tabs.push((offset, spaces));
+                    column += spaces;
+                }
+                _ => column += 1,
+            }
+        }
+        for (offset, _) in &tabs {
+            self.deletion_end(*offset, 1)?;
+        }
+        self.transaction(|table| {
+            for (offset, spaces) in tabs.into_iter().rev() {
+                table.replace(offset, 1, &" ".repeat(spaces))?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Rewrites the indentation of every line to use as many tabs as possible, with a tab
+    /// stop every tab_width columns, followed by spaces for any remainder. Only the
+    /// leading spaces and tabs of each line are affected; spaces after the first other
+    /// character of a line are left alone. A tab_width of zero changes nothing.
+    /// All of the lines are rewritten as a single undo step.
+    /// Returns an error without changing the document if any indentation to be rewritten
+    /// is in a read-only region.
+    pub fn unexpand_tabs(&mut self, tab_width: usize) -> Result<(), EditError> {
+        if tab_width == 0 {
+            return Ok(());
+        }
+        let mut edits = Vec::new();
+        for Range {
+            start,
+            end: line_end,
+        } in self.line_spans()
+        {
+            let mut end = start;
+            let mut column = 0;
+            for c in self.range_chunks(start..line_end).flat_map(str::chars) {
+                match c {
+                    ' ' => column += 1,
+                    '\t' => column += tab_width - column % tab_width,
+                    _ => break,
+                }
+                end += 1;
+            }
+            let indent = "\t".repeat(column / tab_width) + &" ".repeat(column % tab_width);
+            if !self.bytes_from(start).take(end - start).eq(indent.bytes()) {
+                edits.push((start, end - start, indent));
+            }
+        }
+        for (start, len, _) in &edits {
+            self.deletion_end(*start, *len)?;
+        }
+        self.transaction(|table| {
+            for (start, len, indent) in edits.into_iter().rev() {
+                table.replace(start, len, &indent)?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Removes the spaces and tabs at the end of every line, leaving the line endings in
+    /// place, including the '\r' of a "\r\n" line ending, as a single undo step.
+    /// Returns an error without changing the document if any whitespace to be removed is
+    /// in a read-only region.
+    pub fn trim_trailing_whitespace(&mut self) -> Result<(), EditError> {
+        let mut trailing = Vec::new();
+        for (span, line) in self.line_spans().zip(self.lines()) {
+            let content = line.strip_suffix('\r').unwrap_or(&line);
+            let trimmed = content.trim_end_matches([' ', '\t']);
+            if trimmed.len() < content.len() {
+                trailing.push((span.start + trimmed.len(), content.len() - trimmed.len()));
+            }
+        }
+        for (start, len) in &trailing {
+            self.deletion_end(*start, *len)?;
+        }
+        self.transaction(|table| {
+            for (start, len) in trailing.into_iter().rev() {
+                table.delete(start, len)?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Adds an anchor at the specified byte position, clamped to the end of the document,
+    /// that moves as text is inserted and deleted before it, so that it stays pinned to
+    /// the same text. Text inserted exactly at the anchor goes after it, as for Bias::Left.
+    pub fn add_anchor(&mut self, position: usize) -> AnchorId {
+        self.add_anchor_with_bias(position, Bias::Left)
+    }
+
+    /// Adds an anchor as for add_anchor, with bias choosing which side of text inserted
+    /// exactly at the anchor it ends up on.
+    pub fn add_anchor_with_bias(&mut self, position: usize, bias: Bias) -> AnchorId {
+        self.anchors.push(Some(Anchor {
+            position: min(position, self.len()),
+            bias,
+        }));
+        AnchorId(self.anchors.len() - 1)
+    }
+
+    /// The current byte position of an anchor, or None if it has been removed.
+    /// Deleting text containing an anchor moves the anchor to where the text was. Undo
+    /// and redo leave anchors where they are, except to keep them within the document.
+    pub fn anchor_position(&self, id: AnchorId) -> Option<usize> {
+        self.anchors
+            .get(id.0)
+            .copied()
+            .flatten()
+            .map(|anchor| anchor.position)
+    }
+
+    /// Inserts s at the current position of an anchor, as insert does, and moves the
+    /// anchor to after the inserted text whatever its bias, so that repeated inserts at
+    /// the anchor follow each other. Returns an error if the anchor has been removed.
+    pub fn insert_at_anchor(
+        &mut self,
+        id: AnchorId,
+        s: impl AsRef<str>,
+    ) -> Result<Range<usize>, EditError> {
+        let position = self.anchor_position(id).ok_or(EditError::NoSuchAnchor)?;
+        let range = self.insert(position, s)?;
+        if let Some(anchor) = self.anchors[id.0].as_mut() {
+            anchor.position = range.end;
+        }
+        Ok(range)
+    }
+
+    /// Removes an anchor, returning whether it existed.
+    pub fn remove_anchor(&mut self, id: AnchorId) -> bool {
+        self.anchors.get_mut(id.0).and_then(Option::take).is_some()
+    }
+
+    /// Called by every edit before it changes the pieces, to record that removed bytes
+    /// starting at position are being replaced with added bytes. The anchors are moved
+    /// straight away, and the observers are told once the edit is complete.
+    fn text_changed(&mut self, position: usize, removed: usize, added: usize) {
+        self.shift_anchors(position, removed, added);
+        if removed > 0 || added > 0 {
+            let change = Change {
+                removed: position..position + removed,
+                inserted: position..position + added,
+            };
+            self.pending_change = Some(match self.pending_change.take() {
+                Some(previous) => previous.followed_by(&change),
+                None => change,
+            });
+        }
+    }
+
+    /// Registers a function to be called after every change to the text, including
+    /// undo and redo, once the table is consistent again. It is given the table and the
+    /// change, and is called in the order the functions were registered.
+    /// Observers aren't copied when the table is cloned or snapshotted.
+    pub fn on_change(
+        &mut self,
+        observer: impl FnMut(&PieceTable, &Change) + Send + Sync + 'static,
+    ) {
+        self.observers.0.push(Box::new(observer));
+    }
+
+    /// Tells the observers about the change the latest edit made, if it made one.
+    fn notify_change(&mut self) {
+        let Some(change) = self.pending_change.take() else {
+            return;
+        };
+        let mut observers = std::mem::take(&mut self.observers.0);
+        for observer in &mut observers {
+            observer(self, &change);
+        }
+        observers.append(&mut self.observers.0);
+        self.observers.0 = observers;
+    }
+
+    /// Moves the anchors to account for removed bytes starting at position having been
+    /// replaced with added bytes.
+    fn shift_anchors(&mut self, position: usize, removed: usize, added: usize) {
+        let end = position + removed;
+        for anchor in self.anchors.iter_mut().flatten() {
+            if anchor.position >= end && anchor.position > position {
+                anchor.position = anchor.position - removed + added;
+            } else if anchor.position >= position {
+                anchor.position = match anchor.bias {
+                    Bias::Left => position,
+                    Bias::Right => position + added,
+                };
+            }
+        }
+    }
+}
+
+/// Checks whether offset lies on a character boundary of bytes, as str::is_char_boundary
+/// does, that is at either end or at a byte that isn't a UTF-8 continuation byte.
+fn is_utf8_boundary(bytes: &[u8], offset: usize) -> bool {
+    match bytes.get(offset) {
+        Some(byte) => (*byte as i8) >= -0x40,
+        None => offset == bytes.len(),
+    }
+}
+
+/// A step in turning one sequence of characters into another.
+#[derive(Clone, Copy)]
+enum DiffOp {
+    Keep(char),
+    Delete(char),
+    Insert(char),
+}
+
+/// Finds the shortest list of steps that turns a into b, using Myers' algorithm.
+/// For each number of differences d it records how far along each diagonal k
+/// (characters of a used minus characters of b used) the furthest path reaches, then
+/// walks back through those records from the end to recover the path.
+fn shortest_edit(a: &[char], b: &[char]) -> Vec<DiffOp> {
+    let (n, m) = (a.len() as isize, b.len() as isize);
+    let offset = n + m + 1;
+    let mut v = vec![0isize; 2 * offset as usize + 1];
+    let mut trace = Vec::new();
+    'search: for d in 0..=n + m {
+        trace.push(v.clone());
+        for k in (-d..=d).step_by(2) {
+            let index = (k + offset) as usize;
+            let mut x = if k == -d || (k != d && v[index - 1] < v[index + 1]) {
+                v[index + 1]
+            } else {
+                v[index - 1] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[index] = x;
+            if x >= n && y >= m {
+                break 'search;
+            }
+        }
+    }
+
+    let mut ops = Vec::new();
+    let (mut x, mut y) = (n, m);
+    for (d, v) in trace.iter().enumerate().rev() {
+        let d = d as isize;
+        let k = x - y;
+        let index = (k + offset) as usize;
+        let previous_k = if k == -d || (k != d && v[index - 1] < v[index + 1]) {
+            k + 1
+        } else {
+            k - 1
+        };
+        let previous_x = v[(previous_k + offset) as usize];
+        let previous_y = previous_x - previous_k;
+        while x > previous_x && y > previous_y {
+            x -= 1;
+            y -= 1;
+            ops.push(DiffOp::Keep(a[x as usize]));
+        }
+        if d > 0 {
+            if x == previous_x {
+                ops.push(DiffOp::Insert(b[previous_y as usize]));
+            } else {
+                ops.push(DiffOp::Delete(a[previous_x as usize]));
+            }
+        }
+        x = previous_x;
+        y = previous_y;
+    }
+    ops.reverse();
+    ops
+}
+
+impl Default for PieceTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Parsing a piece table never fails, since any text makes a valid document.
+impl FromStr for PieceTable {
+    type Err = Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Self::from_string(s.to_owned()))
+    }
+}
+
+/// Tables are equal if their documents have the same text, however the text is split
+/// into pieces and buffers. Documents of different lengths are rejected without
+/// comparing any text.
+impl PartialEq for PieceTable {
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len() && self.bytes_from(0).eq(other.bytes_from(0))
+    }
+}
+
+impl Eq for PieceTable {}
+
+/// Hashing feeds in the bytes of the document one at a time followed by its length, so
+/// that tables which are equal hash the same way however their pieces are laid out.
+/// The lengths a table caches internally never change its hash, so tables are safe to
+/// use as keys even though clippy's mutable_key_type lint complains about them.
+impl Hash for PieceTable {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.bytes_from(0).for_each(|byte| state.write_u8(byte));
+        state.write_usize(self.len());
+    }
+}
+
+/// Displaying a piece table writes the text of each piece in turn, so the document is
+/// never gathered into a single string first. The pieces of a binary table are written
+/// with any bytes that aren't valid UTF-8 replaced by U+FFFD.
+impl fmt::Display for PieceTable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for piece in &self.pieces {
+            if self.binary {
+                f.write_str(&String::from_utf8_lossy(self.piece_slice(*piece)))?;
+            } else {
+                f.write_str(self.piece_text(*piece))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Writing to a piece table appends the text to the end of the document.
+impl fmt::Write for PieceTable {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.insert(self.len(), s)
+            .map(|_| ())
+            .map_err(|_| fmt::Error)
+    }
+}
+
+/// Extending a piece table appends each string to the end of the document in turn, so
+/// strings appended one after another share a piece.
+impl<'a> Extend<&'a str> for PieceTable {
+    fn extend<I: IntoIterator<Item = &'a str>>(&mut self, iter: I) {
+        for s in iter {
+            // The end of the document is always a character boundary outside any
+            // read-only region, so appending can't fail.
+            let _ = self.insert(self.len(), s);
+        }
+    }
+}
+
+/// Extending a piece table appends each character to the end of the document in turn.
+impl Extend<char> for PieceTable {
+    fn extend<I: IntoIterator<Item = char>>(&mut self, iter: I) {
+        for c in iter {
+            let _ = self.insert_char(self.len(), c);
+        }
+    }
+}
+
+/// Builds a piece table from a sequence of segments, each labelled as either original
+/// text or inserted text. Original segments are stored in the original buffer and
+/// inserted segments in an add buffer, so that the table reports the inserted segments
+/// as changes.
+#[derive(Default)]
+pub struct PieceTableBuilder {
+    original: String,
+    inserted: String,
+    segments: Vec<(bool, Range<usize>)>,
+    buffer_growth: BufferGrowth,
+    normalize_eol: bool,
+}
+
+impl PieceTableBuilder {
+    /// Creates a builder for an empty document.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends a segment of original text to the document.
+    pub fn original(mut self, s: &str) -> Self {
+        let start = self.original.len();
+        self.original += s;
+        self.segments.push((true, start..self.original.len()));
+        self
+    }
+
+    /// Appends a segment of inserted text to the document.
+    pub fn inserted(mut self, s: &str) -> Self {
+        let start = self.inserted.len();
+        self.inserted += s;
+        self.segments.push((false, start..self.inserted.len()));
+        self
+    }
+
+    /// Sets how big the table makes each new add buffer once it is built.
+    pub fn buffer_growth(mut self, growth: BufferGrowth) -> Self {
+        self.buffer_growth = growth;
+        self
+    }
+
+    /// Sets whether the table converts line endings in inserted text to '\n' once it is
+    /// built, as set_normalize_eol does. The segments given to the builder are kept as
+    /// they are.
+    pub fn normalize_eol(mut self, on: bool) -> Self {
+        self.normalize_eol = on;
+        self
+    }
+
+    /// Builds the table, with the segments concatenated in the order they were added.
+    pub fn build(self) -> PieceTable {
+        let mut piece_table = PieceTable::new();
+        piece_table.buffer_growth = self.buffer_growth;
+        piece_table.normalize_eol = self.normalize_eol;
+        if !self.original.is_empty() {
+            piece_table.original_len = Some(self.original.len());
+            piece_table
+                .buffers
+                .push(Arc::new(self.original.into_bytes()));
+        }
+        let inserted_buffer = piece_table.buffers.len();
+        if !self.inserted.is_empty() {
+            piece_table
+                .buffers
+                .push(Arc::new(self.inserted.into_bytes()));
+        }
+        for (original, range) in self.segments {
+            if range.is_empty() {
+                continue;
+            }
+            let piece = Piece {
+                buffer_index: if original { 0 } else { inserted_buffer },
+                start: range.start,
+                end: range.end,
+            };
+            if !piece_table
+                .pieces
+                .last_mut()
+                .is_some_and(|last| last.merge(piece))
+            {
+                piece_table.pieces.push(piece);
+            }
+        }
+        piece_table
+    }
+}
+
+/// A position in a piece table that can step through the document one character at a
+/// time, editing it along the way. Edits are made at the cursor, and the cursor keeps
+/// its position consistent with them, so find-and-modify can be done in a single pass.
+pub struct EditCursor<'a> {
+    table: &'a mut PieceTable,
+    offset: usize,
+}
+
+impl<'a> EditCursor<'a> {
+    /// The byte offset of the cursor in the document.
+    pub fn current_offset(&self) -> usize {
+        self.offset
+    }
+
+    /// The character after the cursor, or None at the end of the document.
+    pub fn peek(&self) -> Option<char> {
+        self.table
+            .char_indices_from(self.offset)
+            .next()
+            .map(|(_, c)| c)
+    }
+
+    /// Moves the cursor past the next character.
+    /// Returns false if the cursor was already at the end of the document.
+    pub fn advance(&mut self) -> bool {
+        match self.peek() {
+            Some(c) => {
+                self.offset += c.len_utf8();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Inserts s at the cursor, leaving the cursor after the inserted text.
+    pub fn insert(&mut self, s: &str) -> Result<(), EditError> {
+        self.offset = self.table.insert(self.offset, s)?.end;
+        Ok(())
+    }
+
+    /// Deletes len bytes after the cursor. The cursor doesn't move.
+    pub fn delete(&mut self, len: usize) -> Result<(), EditError> {
+        self.table.delete(self.offset, len)
+    }
+}
+
+/// A read-only position in a piece table that remembers which piece it is in, so that
+/// moving it costs time proportional to the distance moved rather than the size of the
+/// document.
+pub struct Cursor<'a> {
+    table: &'a PieceTable,
+    loc: Location,
+    position: usize,
+}
+
+impl<'a> Cursor<'a> {
+    /// The byte offset of the cursor in the document, suitable for passing to insert
+    /// and delete.
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    /// Moves the cursor past the next character and returns it, or returns None if the
+    /// cursor is at the end of the document.
+    pub fn next_char(&mut self) -> Option<char> {
+        let piece = *self.table.pieces.get(self.loc.piece_index)?;
+        let c = self.table.piece_text(piece)[self.loc.offset..]
+            .chars()
+            .next()?;
+        self.loc.offset += c.len_utf8();
+        self.position += c.len_utf8();
+        if self.loc.offset == piece.len() {
+            self.loc = Location::new(self.loc.piece_index + 1, 0);
+        }
+        Some(c)
+    }
+
+    /// Moves the cursor back before the previous character and returns it, or returns
+    /// None if the cursor is at the start of the document.
+    pub fn prev_char(&mut self) -> Option<char> {
+        if self.loc.offset == 0 {
+            if self.loc.piece_index == 0 {
+                return None;
+            }
+            self.loc.piece_index -= 1;
+            self.loc.offset = self.table.pieces[self.loc.piece_index].len();
+        }
+        let piece = self.table.pieces[self.loc.piece_index];
+        let c = self.table.piece_text(piece)[..self.loc.offset]
+            .chars()
+            .next_back()?;
+        self.loc.offset -= c.len_utf8();
+        self.position -= c.len_utf8();
+        Some(c)
+    }
+
+    /// Moves the cursor delta characters forwards, or backwards if delta is negative,
+    /// stopping at either end of the document.
+    /// Returns whether the cursor moved the whole distance.
+    pub fn seek(&mut self, delta: isize) -> bool {
+        for _ in 0..delta.unsigned_abs() {
+            let moved = if delta > 0 {
+                self.next_char()
+            } else {
+                self.prev_char()
+            };
+            if moved.is_none() {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// An iterator over the text slice of each piece of a piece table in document order,
+/// returned by chunks and by iterating over a reference to the table.
+pub struct Chunks<'a> {
+    table: &'a PieceTable,
+    pieces: std::slice::Iter<'a, Piece>,
+}
+
+impl<'a> Iterator for Chunks<'a> {
+    type Item = &'a str;
+
+    fn next(&mut self) -> Option<&'a str> {
+        let table = self.table;
+        self.pieces.next().map(|piece| table.piece_text(*piece))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.pieces.size_hint()
+    }
+}
+
+impl<'a> DoubleEndedIterator for Chunks<'a> {
+    fn next_back(&mut self) -> Option<&'a str> {
+        let table = self.table;
+        self.pieces
+            .next_back()
+            .map(|piece| table.piece_text(*piece))
+    }
+}
+
+impl<'a> ExactSizeIterator for Chunks<'a> {}
+
+impl<'a> IntoIterator for &'a PieceTable {
+    type Item = &'a str;
+    type IntoIter = Chunks<'a>;
+
+    fn into_iter(self) -> Chunks<'a> {
+        self.chunks()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn new_should_create_a_piece_table_with_no_buffers_or_pieces() {
+        let piece_table = PieceTable::new();
+
+        assert_eq!(piece_table.pieces.len(), 0);
+        assert_eq!(piece_table.buffers.len(), 0);
+    }
+
+    #[test]
+    fn it_should_append_a_string_to_an_empty_piece_buffer() {
+        let mut piece_table = PieceTable::new();
+
+        piece_table.insert(0, "Hello, World").unwrap();
+
+        assert_eq!(&piece_table.to_string(), "Hello, World");
+    }
+
+    #[test]
+    fn inserting_at_beginning_should_prepend_text() {
+        let mut piece_table = PieceTable::new();
+
+        piece_table.insert(0, "World").unwrap();
+        piece_table.insert(0, "Hello, ").unwrap();
+
+        assert_eq!(&piece_table.to_string(), "Hello, World");
+    }
+
+    #[test]
+    fn inserting_at_end_should_append_text() {
+        let mut piece_table = PieceTable::new();
+
+        piece_table.insert(0, "Hello, ").unwrap();
+        piece_table.insert(7, "World").unwrap();
+
+        assert_eq!(&piece_table.to_string(), "Hello, World");
+    }
+
+    #[test]
+    fn inserting_in_middle_should_split_original_text() {
+        let mut piece_table = PieceTable::new();
+
+        piece_table.insert(0, "Goodbye World").unwrap();
+        piece_table.insert(7, " cruel").unwrap();
+
+        assert_eq!(&piece_table.to_string(), "Goodbye cruel World");
+    }
+
+    #[test]
+    fn delete_from_middle_removes_text() {
+        let mut piece_table = PieceTable::from_string("Hello, World".to_owned());
+
+        piece_table.delete(5, 1).unwrap();
+
+        assert_eq!(&piece_table.to_string(), "Hello World");
+    }
+
+    #[test]
+    fn delete_from_start_removes_text() {
+        let mut piece_table = PieceTable::from_string("Hello, World".to_owned());
+
+        piece_table.delete(0, 7).unwrap();
+
+        assert_eq!(&piece_table.to_string(), "World");
+    }
+
+    #[test]
+    fn delete_from_end_removes_text_without_adding_new_pieces() {
+        let mut piece_table = PieceTable::from_string("Hello, World".to_owned());
+
+        piece_table.delete(5, 7).unwrap();
+
+        assert_eq!(&piece_table.to_string(), "Hello");
+        assert_eq!(piece_table.pieces.len(), 1);
+    }
+
+    #[test]
+    fn delete_whole_piece_removes_piece() {
+        let mut piece_table = PieceTable::from_string("Hello, World".to_owned());
+
+        piece_table.delete(0, 12).unwrap();
+
+        assert_eq!(&piece_table.to_string(), "");
+        assert_eq!(piece_table.pieces.len(), 0);
+    }
+
+    #[test]
+    fn deleting_multiple_pieces_removes_all_pieces() {
+        let mut piece_table = PieceTable::from_string("Hello World".to_owned());
+
+        piece_table.insert(5, ",").unwrap();
+        assert_eq!(piece_table.pieces.len(), 3); //Quick sanity check - if we've not got 3 pieces then the test isn't valid!
+
+        piece_table.delete(2, 10).unwrap();
+
+        assert_eq!(&piece_table.to_string(), "He");
+        assert_eq!(piece_table.pieces.len(), 1);
+    }
+
+    #[test]
+    fn inserting_past_end_inserts_at_end() {
+        let mut piece_table = PieceTable::from_string("Hello, World".to_owned());
+        piece_table.insert_clamped(500, "Boom").unwrap();
+        assert_eq!(&piece_table.to_string(), "Hello, WorldBoom");
+    }
+
+    #[test]
+    fn inserting_past_end_is_out_of_bounds() {
+        let mut piece_table = PieceTable::from_string("Hello, World".to_owned());
+        assert_eq!(
+            piece_table.insert(500, "Boom"),
+            Err(EditError::OutOfBounds(500))
+        );
+        assert_eq!(&piece_table.to_string(), "Hello, World");
+    }
+
+    #[test]
+    fn editing_inside_a_character_is_rejected() {
+        let mut piece_table = PieceTable::from_string("héllo".to_owned());
+        assert_eq!(
+            piece_table.insert(2, "X"),
+            Err(EditError::NotCharBoundary(2))
+        );
+        assert_eq!(piece_table.delete(2, 2), Err(EditError::NotCharBoundary(2)));
+        assert_eq!(piece_table.delete(0, 2), Err(EditError::NotCharBoundary(2)));
+        assert_eq!(&piece_table.to_string(), "héllo");
+    }
+
+    #[test]
+    fn rejected_insert_inside_a_character_leaves_no_bytes_behind() {
+        let mut piece_table = PieceTable::from_string("na\u{ef}ve".to_owned());
+        piece_table.insert(6, " caf\u{e9}").unwrap();
+        let text = piece_table.to_string();
+        let bytes = piece_table.buffer_bytes();
+        let version = piece_table.version();
+
+        for position in [3, 11] {
+            assert_eq!(
+                piece_table.insert(position, "X"),
+                Err(EditError::NotCharBoundary(position))
+            );
+            assert_eq!(
+                piece_table.insert_char(position, '\u{e9}'),
+                Err(EditError::NotCharBoundary(position))
+            );
+        }
+        assert_eq!(piece_table.to_string(), text);
+        assert_eq!(piece_table.buffer_bytes(), bytes);
+        assert_eq!(piece_table.piece_count(), 2);
+        assert_eq!(piece_table.version(), version);
+        assert!(piece_table.undo());
+        assert_eq!(&piece_table.to_string(), "na\u{ef}ve");
+    }
+
+    #[test]
+    fn deleting_across_pieces_into_a_character_is_rejected() {
+        let mut piece_table = PieceTable::from_string("na\u{ef}ve caf\u{e9}".to_owned());
+        piece_table.insert(6, "r\u{e9}sum\u{e9} ").unwrap();
+        let text = piece_table.to_string();
+
+        for (position, len) in [(3, 6), (1, 7), (4, 9), (10, 10), (13, 1)] {
+            assert!(matches!(
+                piece_table.delete(position, len),
+                Err(EditError::NotCharBoundary(_))
+            ));
+        }
+        assert_eq!(piece_table.to_string(), text);
+        assert_eq!(piece_table.validate(), Ok(()));
+    }
+
+    #[test]
+    fn deleting_when_start_is_past_end_of_buffer_does_nothing() {
+        let mut piece_table = PieceTable::from_string("Hello, World".to_owned());
+        assert_eq!(piece_table.delete(500, 1), Err(EditError::OutOfBounds(500)));
+        assert_eq!(&piece_table.to_string(), "Hello, World");
+    }
+
+    #[test]
+    fn deleting_when_it_would_delete_past_the_end_deletes_to_end() {
+        let mut piece_table = PieceTable::from_string("Hello, World".to_owned());
+        piece_table.delete(5, 500).unwrap();
+        assert_eq!(&piece_table.to_string(), "Hello");
+    }
+
+    #[test]
+    fn exceeding_max_pieces_compacts_the_table() {
+        let mut piece_table = PieceTable::from_string("Hello, World".to_owned());
+        piece_table.set_max_pieces(Some(4));
+
+        for i in 0..20 {
+            piece_table.insert_clamped(i * 2, "-").unwrap();
+            assert!(piece_table.pieces.len() <= 4);
+        }
+
+        assert_eq!(&piece_table.to_string(), "-H-e-l-l-o-,- -W-o-r-l-d--------");
+    }
+
+    #[test]
+    fn setting_max_pieces_below_current_count_compacts_immediately() {
+        let mut piece_table = PieceTable::from_string("Hello World".to_owned());
+        piece_table.insert(5, ",").unwrap();
+        assert_eq!(piece_table.pieces.len(), 3);
+
+        piece_table.set_max_pieces(Some(2));
+
+        assert_eq!(piece_table.max_pieces(), Some(2));
+        assert_eq!(piece_table.pieces.len(), 1);
+        assert_eq!(piece_table.buffers.len(), 1);
+        assert_eq!(&piece_table.to_string(), "Hello, World");
+    }
+
+    #[test]
+    fn match_ranges_finds_matches_spanning_pieces() {
+        let mut piece_table = PieceTable::from_string("ab on".to_owned());
+        piece_table.insert(5, "e two one").unwrap();
+
+        let ranges: Vec<_> = piece_table.match_ranges("one").collect();
+        let expected: Vec<_> = piece_table
+            .to_string()
+            .match_indices("one")
+            .map(|(start, m)| start..start + m.len())
+            .collect();
+
+        assert_eq!(ranges, expected);
+        assert_eq!(ranges, vec![3..6, 11..14]);
+    }
+
+    #[test]
+    fn match_ranges_can_stop_early() {
+        let piece_table = PieceTable::from_string("aaaa".to_owned());
+
+        let mut ranges = piece_table.match_ranges("a");
+
+        assert_eq!(ranges.next(), Some(0..1));
+        assert_eq!(ranges.next(), Some(1..2));
+    }
+
+    #[test]
+    fn find_ignore_case_matches_mixed_case_ascii_across_pieces() {
+        let mut piece_table = PieceTable::from_string("Hello, WO".to_owned());
+        piece_table.insert(9, "rLd").unwrap();
+
+        assert_eq!(piece_table.find_ignore_case("world"), Some(7));
+        assert_eq!(piece_table.find_ignore_case("HELLO"), Some(0));
+        assert_eq!(piece_table.find_ignore_case("planet"), None);
+    }
+
+    #[test]
+    fn find_ignore_case_handles_lowercase_forms_of_different_length() {
+        let piece_table = PieceTable::from_string("Visit İstanbul".to_owned());
+
+        assert_eq!(piece_table.find_ignore_case("i\u{307}stanbul"), Some(6));
+        assert_eq!(piece_table.find_ignore_case("i"), Some(1));
+        assert_eq!(piece_table.find_ignore_case("ist"), None);
+    }
+
+    #[test]
+    fn line_chunks_yields_the_pieces_of_a_line() {
+        let mut piece_table = PieceTable::from_string("first\nsecnd\nthird".to_owned());
+        piece_table.insert(9, "o").unwrap();
+
+        let chunks: Vec<_> = piece_table.line_chunks(1).unwrap().collect();
+
+        assert_eq!(chunks, vec!["sec", "o", "nd"]);
+        assert_eq!(
+            piece_table.line_chunks(0).unwrap().collect::<String>(),
+            "first"
+        );
+        assert_eq!(
+            piece_table.line_chunks(2).unwrap().collect::<String>(),
+            "third"
+        );
+        assert!(piece_table.line_chunks(3).is_none());
+    }
+
+    #[test]
+    fn shrink_pieces_releases_spare_capacity() {
+        let mut piece_table = PieceTable::from_string("Hello, World".to_owned());
+        for i in 0..64 {
+            piece_table.insert_clamped(i * 2, "-").unwrap();
+        }
+        piece_table.delete(0, 100).unwrap();
+        let capacity = piece_table.pieces.capacity();
+
+        piece_table.shrink_pieces();
+
+        assert!(piece_table.pieces.capacity() < capacity);
+        assert_eq!(&piece_table.to_string(), "");
+    }
+
+    #[test]
+    fn word_count_counts_words_split_across_pieces_once() {
+        let mut piece_table = PieceTable::from_string("Hello  Wld\tfoo".to_owned());
+        piece_table.insert(8, "or").unwrap();
+        piece_table.insert(0, " ").unwrap();
+
+        assert_eq!(&piece_table.to_string(), " Hello  World\tfoo");
+        assert_eq!(piece_table.word_count(), 3);
+        assert_eq!(piece_table.whitespace_count(), 4);
+    }
+
+    #[test]
+    fn word_count_of_empty_document_is_zero() {
+        let piece_table = PieceTable::new();
+
+        assert_eq!(piece_table.word_count(), 0);
+        assert_eq!(piece_table.whitespace_count(), 0);
+    }
+
+    #[test]
+    fn push_piece_builds_an_exact_layout() {
+        let mut piece_table = PieceTable::from_string("Hello, World".to_owned());
+        piece_table.delete(0, 12).unwrap();
+
+        piece_table.push_piece(0, 7, 12).unwrap();
+        piece_table.push_piece(0, 5, 7).unwrap();
+        piece_table.push_piece(0, 0, 2).unwrap();
+        piece_table.push_piece(0, 2, 5).unwrap();
+
+        assert_eq!(&piece_table.to_string(), "World, Hello");
+        assert_eq!(piece_table.pieces.len(), 4);
+    }
+
+    #[test]
+    fn push_piece_rejects_invalid_pieces() {
+        let mut piece_table = PieceTable::from_string("Héllo".to_owned());
+
+        assert!(piece_table.push_piece(1, 0, 1).is_err());
+        assert!(piece_table.push_piece(0, 3, 3).is_err());
+        assert!(piece_table.push_piece(0, 0, 7).is_err());
+        assert!(piece_table.push_piece(0, 0, 2).is_err());
+        assert_eq!(piece_table.pieces.len(), 1);
+    }
+
+    #[test]
+    fn replace_char_range_replaces_multibyte_characters() {
+        let mut piece_table = PieceTable::from_string("naïve café".to_owned());
+
+        piece_table.replace_char_range(2..3, "i").unwrap();
+        assert_eq!(&piece_table.to_string(), "naive café");
+
+        piece_table
+            .replace_char_range(6..10, "crème brûlée")
+            .unwrap();
+        assert_eq!(&piece_table.to_string(), "naive crème brûlée");
+    }
+
+    #[test]
+    fn write_macro_appends_formatted_text() {
+        use std::fmt::Write;
+        let mut piece_table = PieceTable::from_string("Total".to_owned());
+
+        write!(piece_table, " count: {}", 42).unwrap();
+        writeln!(piece_table, "!").unwrap();
+
+        assert_eq!(&piece_table.to_string(), "Total count: 42!\n");
+    }
+
+    #[test]
+    fn line_of_offset_counts_newlines_before_the_offset() {
+        let mut piece_table = PieceTable::from_string("one\ntwo\nthree".to_owned());
+        piece_table.insert(4, "2\n").unwrap();
+
+        assert_eq!(piece_table.line_of_offset(0), 0);
+        assert_eq!(piece_table.line_of_offset(3), 0);
+        assert_eq!(piece_table.line_of_offset(4), 1);
+        assert_eq!(piece_table.line_of_offset(6), 2);
+        assert_eq!(piece_table.line_of_offset(11), 3);
+        assert_eq!(piece_table.line_of_offset(500), 3);
+    }
+
+    #[test]
+    fn deleting_between_contiguous_pieces_merges_them() {
+        let mut piece_table = PieceTable::from_string("Hello, World".to_owned());
+        piece_table.delete(0, 12).unwrap();
+        piece_table.push_piece(0, 0, 5).unwrap();
+        piece_table.push_piece(0, 7, 12).unwrap();
+        piece_table.push_piece(0, 5, 12).unwrap();
+
+        piece_table.delete(5, 5).unwrap();
+
+        assert_eq!(&piece_table.to_string(), "Hello, World");
+        assert_eq!(piece_table.pieces.len(), 1);
+    }
+
+    #[test]
+    fn to_cow_borrows_an_unfragmented_document() {
+        let piece_table = PieceTable::from_string("Hello, World".to_owned());
+
+        let text = piece_table.to_cow();
+
+        assert!(matches!(text, Cow::Borrowed("Hello, World")));
+    }
+
+    #[test]
+    fn to_cow_allocates_for_a_fragmented_document() {
+        let mut piece_table = PieceTable::from_string("Hello World".to_owned());
+        piece_table.insert(5, ",").unwrap();
+
+        let text = piece_table.to_cow();
+
+        assert!(matches!(text, Cow::Owned(_)));
+        assert_eq!(text, "Hello, World");
+    }
+
+    #[test]
+    fn edits_inside_a_readonly_region_are_rejected() {
+        let mut piece_table = PieceTable::from_string("Dear NAME, hello".to_owned());
+        piece_table.add_readonly_region(0..5);
+
+        assert_eq!(piece_table.insert(2, "X"), Err(EditError::ReadOnly(2)));
+        assert_eq!(piece_table.delete(4, 2), Err(EditError::ReadOnly(4)));
+        assert_eq!(piece_table.delete(0, 1), Err(EditError::ReadOnly(0)));
+
+        assert_eq!(&piece_table.to_string(), "Dear NAME, hello");
+    }
+
+    #[test]
+    fn edits_adjacent_to_a_readonly_region_are_allowed() {
+        let mut piece_table = PieceTable::from_string("Dear NAME, hello".to_owned());
+        piece_table.add_readonly_region(5..9);
+
+        piece_table.insert(5, "Mr ").unwrap();
+        piece_table.insert(12, "!").unwrap();
+        piece_table.delete(0, 5).unwrap();
+        assert_eq!(piece_table.insert(5, "X"), Err(EditError::ReadOnly(5)));
+
+        assert_eq!(&piece_table.to_string(), "Mr NAME!, hello");
+        assert_eq!(piece_table.readonly_regions, vec![3..7]);
+    }
+
+    #[test]
+    fn into_parts_round_trips_through_from_parts() {
+        let mut piece_table = PieceTable::from_string("Hello World".to_owned());
+        piece_table.insert(5, ",").unwrap();
+
+        let (buffers, pieces) = piece_table.into_parts();
+
+        assert_eq!(buffers.len(), 2);
+        assert_eq!(pieces, vec![(0, 0, 5), (1, 0, 1), (0, 5, 11)]);
+        let mut piece_table = PieceTable::from_parts(buffers, pieces).unwrap();
+        assert_eq!(&piece_table.to_string(), "Hello, World");
+        assert_eq!(piece_table.version(), 0);
+        assert!(!piece_table.undo());
+        assert_eq!(&piece_table.to_string(), "Hello, World");
+    }
+
+    #[test]
+    fn empty_table_round_trips_through_from_parts() {
+        let (buffers, pieces) = PieceTable::from_string(String::new()).into_parts();
+
+        let piece_table = PieceTable::from_parts(buffers, pieces).unwrap();
+
+        assert!(piece_table.is_empty());
+        assert_eq!(piece_table.piece_count(), 0);
+        assert_eq!(piece_table.validate(), Ok(()));
+    }
+
+    #[test]
+    fn from_parts_rejects_invalid_pieces() {
+        let result = PieceTable::from_parts(vec!["Hello".to_owned()], vec![(0, 0, 6)]);
+        assert!(result.is_err());
+
+        let result = PieceTable::from_parts(vec!["Hello".to_owned()], vec![(0, 3, 2)]);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn trim_matches_removes_characters_from_both_ends_across_pieces() {
+        let mut piece_table = PieceTable::from_string("\"quoted\"".to_owned());
+        piece_table.insert(0, "'\"").unwrap();
+        piece_table.insert(10, "\"'").unwrap();
+        piece_table.insert(5, "'").unwrap();
+
+        piece_table.trim_matches(|c| c == '"' || c == '\'').unwrap();
+
+        assert_eq!(&piece_table.to_string(), "qu'oted");
+        assert_eq!(piece_table.pieces.len(), 3);
+    }
+
+    #[test]
+    fn trim_matches_can_remove_everything() {
+        let mut piece_table = PieceTable::from_string("\"\"".to_owned());
+        piece_table.insert(1, "\"").unwrap();
+
+        piece_table.trim_matches(|c| c == '"').unwrap();
+
+        assert_eq!(&piece_table.to_string(), "");
+        assert_eq!(piece_table.pieces.len(), 0);
+    }
+
+    #[test]
+    fn tables_with_different_lengths_are_not_equal() {
+        let piece_table = PieceTable::from_string("Hello, World".to_owned());
+        let other = PieceTable::from_string("Hello, World!".to_owned());
+
+        assert!(piece_table != other);
+    }
+
+    #[test]
+    fn tables_with_equal_lengths_are_compared_by_text() {
+        let mut piece_table = PieceTable::from_string("Hello World".to_owned());
+        piece_table.insert(5, ",").unwrap();
+        let same = PieceTable::from_string("Hello, World".to_owned());
+        let different = PieceTable::from_string("Hello; World".to_owned());
+
+        assert!(piece_table == same);
+        assert!(piece_table != different);
+    }
+
+    #[test]
+    fn apply_hunks_matches_manual_edits() {
+        let mut piece_table = PieceTable::from_string("The quick brown fox".to_owned());
+        let mut expected = PieceTable::from_string("The quick brown fox".to_owned());
+
+        piece_table
+            .apply_hunks(&[
+                Hunk {
+                    start: 0,
+                    remove: 3,
+                    insert: "A".to_owned(),
+                },
+                Hunk {
+                    start: 16,
+                    remove: 3,
+                    insert: "dog".to_owned(),
+                },
+                Hunk {
+                    start: 4,
+                    remove: 0,
+                    insert: "very ".to_owned(),
+                },
+            ])
+            .unwrap();
+        expected.delete(16, 3).unwrap();
+        expected.insert(16, "dog").unwrap();
+        expected.insert(4, "very ").unwrap();
+        expected.delete(0, 3).unwrap();
+        expected.insert(0, "A").unwrap();
+
+        assert_eq!(&piece_table.to_string(), "A very quick brown dog");
+        assert!(piece_table == expected);
+    }
+
+    #[test]
+    fn apply_hunks_rejects_overlapping_hunks() {
+        let mut piece_table = PieceTable::from_string("The quick brown fox".to_owned());
+
+        let result = piece_table.apply_hunks(&[
+            Hunk {
+                start: 0,
+                remove: 5,
+                insert: String::new(),
+            },
+            Hunk {
+                start: 4,
+                remove: 1,
+                insert: "Q".to_owned(),
+            },
+        ]);
+
+        assert!(result.is_err());
+        assert_eq!(&piece_table.to_string(), "The quick brown fox");
+    }
+
+    #[test]
+    fn apply_hunks_checks_every_hunk_before_changing_anything() {
+        let mut piece_table = PieceTable::from_string("h\u{e9}llo world".to_owned());
+        let hunks = [
+            Hunk {
+                start: 7,
+                remove: 5,
+                insert: "there".to_owned(),
+            },
+            Hunk {
+                start: 2,
+                remove: 1,
+                insert: String::new(),
+            },
+        ];
+
+        assert_eq!(
+            piece_table.apply_hunks(&hunks),
+            Err(EditError::NotCharBoundary(2))
+        );
+        assert_eq!(&piece_table.to_string(), "h\u{e9}llo world");
+
+        piece_table
+            .apply_hunks(&[
+                Hunk {
+                    start: 0,
+                    remove: 1,
+                    insert: "H".to_owned(),
+                },
+                hunks[0].clone(),
+            ])
+            .unwrap();
+        assert_eq!(&piece_table.to_string(), "H\u{e9}llo there");
+        assert!(piece_table.undo());
+        assert_eq!(&piece_table.to_string(), "h\u{e9}llo world");
+        assert!(!piece_table.undo());
+    }
+
+    #[test]
+    fn apply_hunks_with_only_removals_leaves_no_empty_pieces() {
+        let mut piece_table = PieceTable::from_string("The quick brown fox".to_owned());
+
+        piece_table
+            .apply_hunks(&[Hunk {
+                start: 3,
+                remove: 6,
+                insert: String::new(),
+            }])
+            .unwrap();
+
+        assert_eq!(&piece_table.to_string(), "The brown fox");
+        assert_eq!(piece_table.pieces.len(), 2);
+    }
+
+    #[test]
+    fn first_boundary_violation_reports_a_misaligned_piece() {
+        let mut piece_table = PieceTable::from_string("naïve café".to_owned());
+        assert_eq!(piece_table.first_boundary_violation(), None);
+
+        piece_table.pieces = vec![
+            Piece {
+                buffer_index: 0,
+                start: 0,
+                end: 3,
+            },
+            Piece {
+                buffer_index: 0,
+                start: 3,
+                end: 7,
+            },
+            Piece {
+                buffer_index: 0,
+                start: 7,
+                end: 12,
+            },
+        ];
+
+        assert_eq!(piece_table.first_boundary_violation(), Some((0, 3)));
+    }
+
+    #[test]
+    fn cached_line_count_follows_edits() {
+        let mut piece_table = PieceTable::from_string("one\ntwo".to_owned());
+        assert_eq!(piece_table.cached_line_count(), 2);
+
+        piece_table.insert(3, "\nthree\n").unwrap();
+        assert_eq!(piece_table.cached_line_count(), 4);
+        assert_eq!(piece_table.cached_line_count(), piece_table.line_count());
+
+        piece_table.delete(0, 5).unwrap();
+        assert_eq!(piece_table.cached_line_count(), 3);
+        assert_eq!(piece_table.cached_line_count(), piece_table.line_count());
+    }
+
+    #[test]
+    fn insert_accepts_owned_and_borrowed_strings() {
+        let mut piece_table = PieceTable::new();
+        let world = String::from("World");
+
+        piece_table.insert(0, String::from("Hello")).unwrap();
+        piece_table.insert(5, Cow::Borrowed(", ")).unwrap();
+        piece_table.insert(7, &world).unwrap();
+
+        assert_eq!(&piece_table.to_string(), "Hello, World");
+    }
+
+    #[test]
+    fn delete_all_removes_every_occurrence_including_across_pieces() {
+        let mut piece_table = PieceTable::from_string("a-TOKb-TOKc-TO".to_owned());
+        piece_table.insert(14, "Kd-TOK").unwrap();
+
+        let count = piece_table.delete_all("-TOK");
+
+        assert_eq!(count, 4);
+        assert_eq!(&piece_table.to_string(), "abcd");
+    }
+
+    #[test]
+    fn styled_chunks_split_at_pieces_and_styles() {
+        let mut piece_table = PieceTable::from_string("let x = 42;".to_owned());
+        piece_table.insert(4, "mut ").unwrap();
+        let styles = [(0..3, "keyword"), (12..14, "number")];
+
+        let chunks: Vec<_> = piece_table.styled_chunks(&styles).collect();
+
+        assert_eq!(
+            chunks,
+            vec![
+                ("let", Some(&"keyword")),
+                (" ", None),
+                ("mut ", None),
+                ("x = ", None),
+                ("42", Some(&"number")),
+                (";", None),
+            ]
+        );
+    }
+
+    #[test]
+    fn piece_origin_shows_where_pieces_point() {
+        let mut piece_table = PieceTable::from_string("Hello World".to_owned());
+        assert_eq!(piece_table.piece_origin(0), Some((0, 0..11)));
+        assert_eq!(piece_table.piece_origin(1), None);
+
+        piece_table.insert(5, ",").unwrap();
+
+        assert_eq!(piece_table.piece_origin(0), Some((0, 0..5)));
+        assert_eq!(piece_table.piece_origin(1), Some((1, 0..1)));
+        assert_eq!(piece_table.piece_origin(2), Some((0, 5..11)));
+    }
+
+    #[test]
+    fn inserting_with_normalize_eol_converts_line_endings() {
+        let mut piece_table = PieceTable::new();
+        piece_table.set_normalize_eol(true);
+
+        piece_table.insert(0, "one\r\ntwo\rthree\n").unwrap();
+
+        assert_eq!(&piece_table.to_string(), "one\ntwo\nthree\n");
+    }
+
+    #[test]
+    fn inserting_without_normalize_eol_keeps_line_endings() {
+        let mut piece_table = PieceTable::new();
+
+        piece_table.insert(0, "one\r\ntwo\rthree\n").unwrap();
+
+        assert_eq!(&piece_table.to_string(), "one\r\ntwo\rthree\n");
+    }
+
+    #[test]
+    fn char_indices_in_range_reports_document_offsets_across_pieces() {
+        let mut piece_table = PieceTable::from_string("héllo wörld".to_owned());
+        piece_table.insert(7, "big ").unwrap();
+
+        let chars: Vec<_> = piece_table.char_indices_in_range(3..14).collect();
+        let text = piece_table.to_string();
+        let expected: Vec<_> = text[3..14]
+            .char_indices()
+            .map(|(offset, c)| (offset + 3, c))
+            .collect();
+
+        assert_eq!(chars, expected);
+        assert_eq!(chars.first(), Some(&(3, 'l')));
+        assert_eq!(chars.last(), Some(&(12, 'ö')));
+    }
+
+    #[test]
+    fn builder_places_segments_in_original_and_add_buffers() {
+        let piece_table = PieceTableBuilder::new()
+            .original("Hello ")
+            .inserted("brave ")
+            .inserted("new ")
+            .original("World")
+            .build();
+
+        assert_eq!(&piece_table.to_string(), "Hello brave new World");
+        assert_eq!(piece_table.pieces.len(), 3);
+        assert_eq!(piece_table.change_regions(), vec![6..16]);
+    }
+
+    #[test]
+    fn inserted_text_is_reported_as_a_change_region() {
+        let mut piece_table = PieceTable::from_string("Hello World".to_owned());
+        assert_eq!(piece_table.change_regions(), vec![]);
+
+        piece_table.insert(5, ",").unwrap();
+        piece_table.insert(12, "!").unwrap();
+
+        assert_eq!(piece_table.change_regions(), vec![5..6, 12..13]);
+    }
+
+    #[test]
+    fn offset_at_fraction_snaps_to_line_starts() {
+        let mut piece_table = PieceTable::from_string("line one\nline two\n".to_owned());
+        piece_table.insert(18, "line three").unwrap();
+
+        assert_eq!(piece_table.offset_at_fraction(0.0), 0);
+        assert_eq!(piece_table.offset_at_fraction(0.5), 9);
+        assert_eq!(piece_table.offset_at_fraction(1.0), 18);
+        assert_eq!(piece_table.offset_at_fraction(7.0), 18);
+    }
+
+    #[test]
+    fn append_table_moves_buffers_and_remaps_pieces() {
+        let mut piece_table = PieceTable::from_string("Hello".to_owned());
+        let mut other = PieceTable::from_string(" World".to_owned());
+        other.insert(0, ",").unwrap();
+
+        piece_table.append_table(other);
+
+        assert_eq!(&piece_table.to_string(), "Hello, World");
+        assert_eq!(piece_table.buffers.len(), 3);
+        assert_eq!(piece_table.piece_origin(1), Some((2, 0..1)));
+        assert_eq!(piece_table.piece_origin(2), Some((1, 0..6)));
+    }
+
+    #[test]
+    fn find_from_only_matches_occurrences_starting_after_the_offset() {
+        let mut piece_table = PieceTable::from_string("abc abc ab".to_owned());
+        piece_table.insert(10, "c").unwrap();
+
+        assert_eq!(piece_table.find_from(0, "abc"), Some(0));
+        assert_eq!(piece_table.find_from(1, "abc"), Some(4));
+        assert_eq!(piece_table.find_from(5, "abc"), Some(8));
+        assert_eq!(piece_table.find_from(9, "abc"), None);
+    }
+
+    #[test]
+    fn typing_past_the_capacity_of_a_buffer_keeps_extending_the_same_piece() {
+        let mut piece_table = PieceTable::from_string("ab".to_owned());
+
+        for i in 0..100 {
+            piece_table.insert(1 + i, "x").unwrap();
+        }
+
+        assert_eq!(piece_table.pieces.len(), 3);
+        assert_eq!(piece_table.buffers.len(), 2);
+        assert_eq!(piece_table.to_string(), format!("a{}b", "x".repeat(100)));
+    }
+
+    #[test]
+    fn to_lines_matches_str_lines() {
+        let mut piece_table = PieceTable::from_string("one\ntwo\n\nfour".to_owned());
+        piece_table.insert(5, "w").unwrap();
+        piece_table.insert(9, "3\n").unwrap();
+
+        let expected: Vec<String> = piece_table.to_string().lines().map(String::from).collect();
+
+        assert_eq!(piece_table.to_lines(), expected);
+    }
+
+    #[test]
+    fn to_lines_includes_an_empty_last_line() {
+        assert_eq!(
+            PieceTable::from_string("one\n".to_owned()).to_lines(),
+            vec!["one", ""]
+        );
+        assert_eq!(PieceTable::new().to_lines(), vec![""]);
+    }
+
+    #[test]
+    fn clamp_range_limits_ranges_to_the_document() {
+        let piece_table = PieceTable::from_string("Hello".to_owned());
+
+        assert_eq!(piece_table.clamp_range(2..50), 2..5);
+        assert_eq!(piece_table.clamp_range(20..50), 5..5);
+        #[allow(clippy::reversed_empty_ranges)]
+        let inverted = 4..1;
+        assert_eq!(piece_table.clamp_range(inverted), 4..4);
+    }
+
+    #[test]
+    fn clamp_range_widens_ranges_to_character_boundaries() {
+        let mut piece_table = PieceTable::from_string("aéb".to_owned());
+        piece_table.insert(4, "ü").unwrap();
+
+        assert_eq!(piece_table.clamp_range(2..5), 1..6);
+        assert_eq!(piece_table.clamp_range(1..3), 1..3);
+        assert_eq!(
+            piece_table.char_indices_in_range(2..3).collect::<Vec<_>>(),
+            vec![(1, 'é')]
+        );
+    }
+
+    #[test]
+    fn count_bytes_counts_newlines_across_pieces() {
+        let mut piece_table = PieceTable::from_string("one\ntwo\nthree".to_owned());
+        piece_table.insert(4, "\n\n").unwrap();
+
+        let newlines = piece_table.count_bytes(|byte| byte == b'\n');
+
+        assert_eq!(newlines, 4);
+        assert_eq!(newlines, piece_table.line_count() - 1);
+    }
+
+    #[test]
+    fn appending_uses_spare_capacity_in_the_original_buffer() {
+        let mut original = String::with_capacity(64);
+        original += "Hello";
+        let mut piece_table = PieceTable::from_string(original);
+
+        piece_table.insert(5, ", World").unwrap();
+
+        assert_eq!(&piece_table.to_string(), "Hello, World");
+        assert_eq!(piece_table.pieces.len(), 1);
+        assert_eq!(piece_table.buffers.len(), 1);
+        assert_eq!(piece_table.change_regions(), vec![5..12]);
+    }
+
+    #[test]
+    fn split_off_char_splits_at_a_character_index() {
+        let mut piece_table = PieceTable::from_string("größer".to_owned());
+        piece_table.insert(0, "ü").unwrap();
+
+        let tail = piece_table.split_off_char(3);
+
+        assert_eq!(&piece_table.to_string(), "ügr");
+        assert_eq!(&tail.to_string(), "ößer");
+        assert_eq!(&piece_table.split_off_char(10).to_string(), "");
+    }
+
+    #[test]
+    fn version_increments_on_edits_only() {
+        let mut piece_table = PieceTable::from_string("Hello World".to_owned());
+        assert_eq!(piece_table.version(), 0);
+
+        piece_table.insert(5, ",").unwrap();
+        assert_eq!(piece_table.version(), 1);
+
+        piece_table.delete(0, 1).unwrap();
+        assert_eq!(piece_table.version(), 2);
+
+        piece_table.to_string();
+        piece_table.find_from(0, "World");
+        piece_table.cached_line_count();
+        assert_eq!(piece_table.version(), 2);
+    }
+
+    #[test]
+    fn apply_if_version_applies_edits_at_the_expected_version() {
+        let mut piece_table = PieceTable::from_string("Hello World".to_owned());
+        let version = piece_table.version();
+
+        let result = piece_table.apply_if_version(
+            version,
+            &Edit::Insert {
+                pos: 5,
+                text: ",".to_owned(),
+            },
+        );
+
+        assert_eq!(result, Ok(piece_table.version()));
+        assert!(piece_table.version() > version);
+        assert_eq!(&piece_table.to_string(), "Hello, World");
+    }
+
+    #[test]
+    fn apply_if_version_rejects_stale_edits() {
+        let mut piece_table = PieceTable::from_string("Hello World".to_owned());
+        let version = piece_table.version();
+        piece_table.insert(0, "Oh ").unwrap();
+
+        let result = piece_table.apply_if_version(version, &Edit::Delete { pos: 0, len: 5 });
+
+        assert_eq!(result, Err(piece_table.version()));
+        assert_eq!(&piece_table.to_string(), "Oh Hello World");
+    }
+
+    #[test]
+    fn strip_prefix_removes_a_prefix_spanning_pieces() {
+        let mut piece_table = PieceTable::from_string("ix: value".to_owned());
+        piece_table.insert(0, "pref").unwrap();
+
+        assert!(!piece_table.strip_prefix("prefix! "));
+        assert!(piece_table.strip_prefix("prefix: "));
+        assert_eq!(&piece_table.to_string(), "value");
+    }
+
+    #[test]
+    fn strip_suffix_removes_a_suffix_spanning_pieces() {
+        let mut piece_table = PieceTable::from_string("value;\r".to_owned());
+        piece_table.insert(7, "\n").unwrap();
+
+        assert!(!piece_table.strip_suffix("\n\n"));
+        assert!(!piece_table.strip_suffix("a much longer suffix"));
+        assert!(piece_table.strip_suffix(";\r\n"));
+        assert_eq!(&piece_table.to_string(), "value");
+    }
+
+    #[test]
+    fn buffers_touched_by_line_lists_buffers_of_an_edited_line() {
+        let mut piece_table = PieceTable::from_string("first\nsecnd\nthird".to_owned());
+        piece_table.insert(9, "o").unwrap();
+
+        assert_eq!(piece_table.buffers_touched_by_line(0), vec![0]);
+        assert_eq!(piece_table.buffers_touched_by_line(1), vec![0, 1]);
+        assert_eq!(piece_table.buffers_touched_by_line(3), Vec::<usize>::new());
+    }
+
+    #[test]
+    fn reserve_exact_grows_the_add_buffer_in_place() {
+        let mut piece_table = PieceTable::new();
+        piece_table.insert(0, "abc").unwrap();
+        let spare = piece_table.buffers[0].capacity() - piece_table.buffers[0].len();
+
+        piece_table.reserve_exact(spare + 100);
+
+        assert_eq!(piece_table.buffers.len(), 1);
+        assert!(piece_table.buffers[0].capacity() >= 3 + spare + 100);
+    }
+
+    #[test]
+    fn reserve_exact_adds_a_buffer_after_the_original() {
+        let mut piece_table = PieceTable::from_string("Hello".to_owned());
+
+        piece_table.reserve_exact(10);
+
+        assert_eq!(piece_table.buffers.len(), 2);
+        assert!(piece_table.buffers[1].capacity() >= 10);
+    }
+
+    #[test]
+    fn reserve_follows_the_growth_policy_for_new_buffers() {
+        let mut piece_table = PieceTable::from_string("Hello".to_owned());
+
+        piece_table.reserve(3);
+        assert_eq!(piece_table.buffers.len(), 2);
+        assert!(piece_table.buffers[1].capacity() >= 5);
+
+        piece_table.insert(5, " Wo").unwrap();
+        assert_eq!(piece_table.buffers.len(), 2);
+        piece_table.reserve(100);
+        assert_eq!(piece_table.buffers.len(), 2);
+        assert!(piece_table.buffers[1].capacity() >= 103);
+        piece_table.insert(8, "rld!").unwrap();
+        assert_eq!(&piece_table.to_string(), "Hello World!");
+        assert_eq!(piece_table.pieces.len(), 2);
+    }
+
+    #[test]
+    fn edit_cursor_replaces_while_walking_the_document() {
+        let mut piece_table = PieceTable::from_string("banana".to_owned());
+        piece_table.insert(6, " bar").unwrap();
+
+        let mut cursor = piece_table.edit_cursor();
+        while let Some(c) = cursor.peek() {
+            if c == 'a' {
+                cursor.delete(1).unwrap();
+                cursor.insert("bb").unwrap();
+            } else {
+                cursor.advance();
+            }
+        }
+        assert_eq!(cursor.current_offset(), 14);
+        assert!(!cursor.advance());
+
+        assert_eq!(&piece_table.to_string(), "bbbnbbnbb bbbr");
+    }
+
+    #[test]
+    fn origin_runs_collapse_adjacent_pieces_from_the_same_buffer() {
+        let mut piece_table = PieceTable::from_string("Hello World".to_owned());
+        piece_table.delete(5, 1).unwrap();
+        piece_table.insert(10, "?").unwrap();
+        assert_eq!(piece_table.pieces.len(), 3);
+
+        let runs: Vec<_> = piece_table.origin_runs().collect();
+
+        assert_eq!(runs, vec![(0, 0..10), (1, 10..11)]);
+    }
+
+    #[test]
+    fn truncate_chars_limits_the_number_of_characters() {
+        let mut piece_table = PieceTable::from_string("ça va".to_owned());
+        piece_table.insert(6, " über").unwrap();
+
+        piece_table.truncate_chars(20).unwrap();
+        assert_eq!(&piece_table.to_string(), "ça va über");
+
+        piece_table.truncate_chars(7).unwrap();
+        assert_eq!(&piece_table.to_string(), "ça va ü");
+    }
+
+    #[test]
+    fn pieces_per_buffer_counts_pieces_referencing_each_buffer() {
+        let mut piece_table = PieceTable::from_string("Hello World".to_owned());
+        piece_table.insert(5, ",").unwrap();
+        piece_table.insert(0, ">").unwrap();
+        piece_table.delete(9, 1).unwrap();
+
+        let counts = piece_table.pieces_per_buffer();
+
+        assert_eq!(counts, vec![3, 2]);
+        assert_eq!(counts.iter().sum::<usize>(), piece_table.pieces.len());
+    }
+
+    #[test]
+    fn word_boundaries_from_inside_a_word() {
+        let mut piece_table = PieceTable::from_string("one tree".to_owned());
+        piece_table.insert(5, "h").unwrap();
+        piece_table.insert(9, " four").unwrap();
+
+        assert_eq!(&piece_table.to_string(), "one three four");
+        assert_eq!(piece_table.word_start(6), 4);
+        assert_eq!(piece_table.word_end(6), 9);
+    }
+
+    #[test]
+    fn word_boundaries_at_a_space() {
+        let piece_table = PieceTable::from_string("one  two".to_owned());
+
+        assert_eq!(piece_table.word_start(3), 0);
+        assert_eq!(piece_table.word_end(3), 3);
+        assert_eq!(piece_table.word_start(4), 4);
+        assert_eq!(piece_table.word_end(4), 4);
+        assert_eq!(piece_table.word_end(5), 8);
+    }
+
+    #[test]
+    fn word_boundaries_at_document_edges() {
+        let piece_table = PieceTable::from_string("héllo wörld".to_owned());
+
+        assert_eq!(piece_table.word_start(0), 0);
+        assert_eq!(piece_table.word_end(0), 6);
+        assert_eq!(piece_table.word_start(13), 7);
+        assert_eq!(piece_table.word_end(13), 13);
+        assert_eq!(piece_table.word_end(100), 13);
+    }
+
+    #[cfg(feature = "rope")]
+    #[test]
+    fn rope_chunks_round_trip() {
+        let chunks = vec!["Hello".to_owned(), ", ".to_owned(), "World".to_owned()];
+        let mut piece_table = PieceTable::from_rope_chunks(chunks);
+        piece_table.insert(12, "!").unwrap();
+
+        let chunks: Vec<String> = piece_table.rope_chunks().map(String::from).collect();
+        let round_tripped = PieceTable::from_rope_chunks(chunks);
+
+        assert_eq!(&round_tripped.to_string(), "Hello, World!");
+    }
+
+    #[test]
+    fn truncate_take_inside_a_piece_returns_the_tail() {
+        let mut piece_table = PieceTable::from_string("Hello World".to_owned());
+        piece_table.insert(5, ",").unwrap();
+
+        let tail = piece_table.truncate_take(3).unwrap();
+
+        assert_eq!(&tail, "lo, World");
+        assert_eq!(&piece_table.to_string(), "Hel");
+    }
+
+    #[test]
+    fn truncate_take_at_a_piece_boundary_returns_the_tail() {
+        let mut piece_table = PieceTable::from_string("Hello World".to_owned());
+        piece_table.insert(5, ",").unwrap();
+
+        let tail = piece_table.truncate_take(6).unwrap();
+
+        assert_eq!(&tail, " World");
+        assert_eq!(&piece_table.to_string(), "Hello,");
+        assert_eq!(&piece_table.truncate_take(10).unwrap(), "");
+    }
+
+    #[test]
+    fn replace_first_only_replaces_the_first_occurrence() {
+        let mut piece_table = PieceTable::from_string("cat, cat, c".to_owned());
+        piece_table.insert(11, "at").unwrap();
+
+        assert!(piece_table.replace_first("cat", "dog"));
+        assert_eq!(&piece_table.to_string(), "dog, cat, cat");
+        assert!(!piece_table.replace_first("bird", "dog"));
+    }
+
+    #[test]
+    fn replace_last_only_replaces_the_last_occurrence() {
+        let mut piece_table = PieceTable::from_string("cat, cat, c".to_owned());
+        piece_table.insert(11, "at").unwrap();
+
+        assert!(piece_table.replace_last("cat", "mouse"));
+        assert_eq!(&piece_table.to_string(), "cat, cat, mouse");
+        assert!(!piece_table.replace_last("bird", "dog"));
+    }
+
+    #[test]
+    fn line_char_len_counts_characters_not_bytes() {
+        let mut piece_table = PieceTable::from_string("café\n\nnaïve".to_owned());
+        piece_table.insert(0, "déjà ").unwrap();
+
+        assert_eq!(piece_table.line_char_len(0), Some(9));
+        assert_eq!(piece_table.line_char_len(1), Some(0));
+        assert_eq!(piece_table.line_char_len(2), Some(5));
+        assert_eq!(piece_table.line_char_len(3), None);
+    }
+
+    #[test]
+    fn compact_with_chunk_size_keeps_buffers_within_the_chunk_size() {
+        let mut piece_table = PieceTable::from_string("Hello World, ".to_owned());
+        piece_table.insert(13, "héllo wörld").unwrap();
+        piece_table.delete(5, 1).unwrap();
+
+        piece_table.compact_with_chunk_size(4);
+
+        assert_eq!(&piece_table.to_string(), "HelloWorld, héllo wörld");
+        assert!(piece_table.buffers.iter().all(|buffer| buffer.len() <= 4));
+        assert_eq!(piece_table.buffers.len(), piece_table.pieces.len());
+        assert_eq!(piece_table.buffers.len(), 7);
+    }
+
+    #[test]
+    fn compact_with_chunk_size_never_splits_a_character() {
+        let mut piece_table = PieceTable::from_string("äöü".to_owned());
+
+        piece_table.compact_with_chunk_size(1);
+
+        assert_eq!(
+            piece_table.buffers,
+            vec![
+                Arc::new("ä".as_bytes().to_vec()),
+                Arc::new("ö".as_bytes().to_vec()),
+                Arc::new("ü".as_bytes().to_vec())
+            ]
+        );
+        assert_eq!(&piece_table.to_string(), "äöü");
+    }
+
+    #[test]
+    fn is_line_empty_checks_line_lengths() {
+        let mut piece_table = PieceTable::from_string("one\n\n".to_owned());
+        piece_table.insert(4, "\nfour").unwrap();
+
+        assert_eq!(piece_table.is_line_empty(0), Some(false));
+        assert_eq!(piece_table.is_line_empty(1), Some(true));
+        assert_eq!(piece_table.is_line_empty(2), Some(false));
+        assert_eq!(piece_table.is_line_empty(3), Some(true));
+        assert_eq!(piece_table.is_line_empty(4), None);
+    }
+
+    #[test]
+    fn splice_table_replaces_a_range_with_another_table() {
+        let mut piece_table = PieceTable::from_string("Hello cruel World".to_owned());
+        piece_table.insert(5, ",").unwrap();
+        let mut other = PieceTable::from_string("brave".to_owned());
+        other.insert(5, " new").unwrap();
+
+        piece_table.splice_table(7..12, &other).unwrap();
+
+        assert_eq!(&piece_table.to_string(), "Hello, brave new World");
+        assert_eq!(&other.to_string(), "brave new");
+        assert_eq!(
+            piece_table.splice_table(30..31, &other),
+            Err(EditError::OutOfBounds(30))
+        );
+    }
+
+    #[test]
+    fn char_based_edits_use_character_indices() {
+        let mut piece_table = PieceTable::from_string("héllo wörld".to_owned());
+        piece_table.insert(6, "→").unwrap();
+
+        piece_table.insert_chars(9, "ß").unwrap();
+        assert_eq!(&piece_table.to_string(), "héllo→ wößrld");
+
+        piece_table.delete_chars(1, 5).unwrap();
+        assert_eq!(&piece_table.to_string(), "h wößrld");
+
+        piece_table.delete_chars(5, 10).unwrap();
+        piece_table.insert_chars(20, "!").unwrap();
+        assert_eq!(&piece_table.to_string(), "h wöß!");
+    }
+
+    #[test]
+    fn count_chars_counts_matching_characters_across_pieces() {
+        let mut piece_table = PieceTable::from_string("Hello World".to_owned());
+        piece_table.insert(5, ", ÉLAN and").unwrap();
+
+        assert_eq!(piece_table.count_chars(|c| c.is_ascii_uppercase()), 5);
+        assert_eq!(piece_table.count_chars(|c| c.is_uppercase()), 6);
+    }
+
+    #[test]
+    fn len_follows_edits() {
+        let mut piece_table = PieceTable::new();
+        assert_eq!(piece_table.len(), 0);
+        assert!(piece_table.is_empty());
+
+        piece_table.insert(0, "Hello World").unwrap();
+        piece_table.insert(5, ",").unwrap();
+        assert_eq!(piece_table.len(), 12);
+        assert!(!piece_table.is_empty());
+
+        piece_table.delete(0, 7).unwrap();
+        assert_eq!(piece_table.len(), 5);
+        let tail = piece_table.split_off(2);
+        assert_eq!((piece_table.len(), tail.len()), (2, 3));
+
+        piece_table.delete(0, 2).unwrap();
+        assert!(piece_table.is_empty());
+    }
+
+    #[test]
+    fn deleting_a_whole_piece_removes_it_without_splitting() {
+        let mut piece_table = PieceTable::from_string("Hello World".to_owned());
+        piece_table.insert(5, ",").unwrap();
+        assert_eq!(piece_table.pieces.len(), 3);
+        let splits = piece_table.splits;
+
+        piece_table.delete(5, 1).unwrap();
+
+        assert_eq!(piece_table.splits, splits);
+        assert_eq!(piece_table.pieces.len(), 1);
+        assert_eq!(&piece_table.to_string(), "Hello World");
+    }
+
+    #[test]
+    fn display_writes_the_document_across_pieces() {
+        let mut piece_table = PieceTable::from_string("Hello World".to_owned());
+        piece_table.insert(5, ",").unwrap();
+
+        assert_eq!(format!("[{}]", piece_table), "[Hello, World]");
+        assert_eq!(format!("{}", PieceTable::new()), "");
+    }
+
+    #[test]
+    fn content_hash_depends_only_on_the_text() {
+        let mut piece_table = PieceTable::from_string("Hello World".to_owned());
+        piece_table.insert(5, ",").unwrap();
+        let same = PieceTable::from_string("Hello, World".to_owned());
+        assert_eq!(piece_table.content_hash(), same.content_hash());
+
+        piece_table.insert(12, "!").unwrap();
+        assert_ne!(piece_table.content_hash(), same.content_hash());
+    }
+
+    #[test]
+    fn undo_and_redo_step_through_edits() {
+        let mut piece_table = PieceTable::from_string("Hello World".to_owned());
+        piece_table.insert(5, ",").unwrap();
+        piece_table.delete(0, 7).unwrap();
+        assert_eq!(&piece_table.to_string(), "World");
+
+        assert!(piece_table.undo());
+        assert_eq!(&piece_table.to_string(), "Hello, World");
+        assert!(piece_table.undo());
+        assert_eq!(&piece_table.to_string(), "Hello World");
+        assert!(!piece_table.undo());
+
+        assert!(piece_table.redo());
+        assert_eq!(&piece_table.to_string(), "Hello, World");
+        piece_table.insert(12, "!").unwrap();
+        assert!(!piece_table.redo());
+        assert_eq!(&piece_table.to_string(), "Hello, World!");
+    }
+
+    #[test]
+    fn undo_history_is_bounded_by_the_history_limit() {
+        let mut piece_table = PieceTable::with_history_limit(2);
+        for s in ["a", "b", "c"] {
+            piece_table.insert(piece_table.len(), s).unwrap();
+        }
+
+        assert!(piece_table.undo());
+        assert!(piece_table.undo());
+        assert!(!piece_table.undo());
+        assert_eq!(&piece_table.to_string(), "a");
+    }
+
+    #[test]
+    fn splice_table_is_undone_in_one_step() {
+        let mut piece_table = PieceTable::from_string("Hello cruel World".to_owned());
+        let other = PieceTable::from_string("brave".to_owned());
+
+        piece_table.splice_table(6..11, &other).unwrap();
+        piece_table.splice_table(0..0, &other).unwrap();
+        assert!(piece_table.undo());
+        assert_eq!(&piece_table.to_string(), "Hello brave World");
+        assert!(piece_table.undo());
+        assert_eq!(&piece_table.to_string(), "Hello cruel World");
+    }
+
+    #[test]
+    fn splice_table_is_reported_as_one_change() {
+        use std::sync::Mutex;
+
+        let changes = Arc::new(Mutex::new(Vec::new()));
+        let mut piece_table = PieceTable::from_string("Hello cruel World".to_owned());
+        let seen = Arc::clone(&changes);
+        piece_table.on_change(move |_, change| seen.lock().unwrap().push(change.clone()));
+        let other = PieceTable::from_string("brave new".to_owned());
+
+        piece_table.splice_table(6..11, &other).unwrap();
+
+        assert_eq!(&piece_table.to_string(), "Hello brave new World");
+        assert_eq!(piece_table.version(), 1);
+        assert_eq!(
+            *changes.lock().unwrap(),
+            vec![Change {
+                removed: 6..11,
+                inserted: 6..15,
+            }]
+        );
+    }
+
+    #[test]
+    fn compacting_forgets_the_undo_history() {
+        let mut piece_table = PieceTable::from_string("Hello World".to_owned());
+        piece_table.insert(5, ",").unwrap();
+
+        piece_table.compact();
+
+        assert!(!piece_table.undo());
+        assert_eq!(&piece_table.to_string(), "Hello, World");
+    }
+
+    #[test]
+    fn chunks_follow_the_pieces_after_edits() {
+        let mut piece_table = PieceTable::from_string("Hello World".to_owned());
+        piece_table.insert(5, ",").unwrap();
+        piece_table.delete(0, 1).unwrap();
+        piece_table.insert(0, "J").unwrap();
+
+        let chunks: Vec<&str> = piece_table.chunks().collect();
+
+        assert_eq!(chunks, vec!["J", "ello", ",", " World"]);
+    }
+
+    #[test]
+    fn bytes_stream_across_pieces() {
+        let mut piece_table = PieceTable::from_string("Hello World".to_owned());
+        piece_table.insert(5, ",").unwrap();
+        piece_table.insert(0, "é").unwrap();
+
+        let bytes: Vec<u8> = piece_table.bytes().collect();
+
+        assert_eq!(bytes, "éHello, World".as_bytes());
+        assert_eq!(PieceTable::new().bytes().count(), 0);
+    }
+
+    #[test]
+    fn char_indices_report_document_offsets() {
+        let mut piece_table = PieceTable::from_string("héllo".to_owned());
+        piece_table.insert(3, "→").unwrap();
+
+        let chars: String = piece_table.chars().collect();
+        let indices: Vec<(usize, char)> = piece_table.char_indices().collect();
+
+        assert_eq!(&chars, "hé→llo");
+        assert_eq!(
+            indices,
+            vec![(0, 'h'), (1, 'é'), (3, '→'), (6, 'l'), (7, 'l'), (8, 'o')]
+        );
+    }
+
+    #[test]
+    fn assert_text_eq_compares_a_fragmented_document() {
+        let mut piece_table = PieceTable::from_string("Hello World".to_owned());
+        piece_table.insert(5, ",").unwrap();
+        piece_table.insert(12, "!").unwrap();
+        piece_table.delete(0, 1).unwrap();
+
+        assert!(piece_table.assert_text_eq("ello, World!"));
+        assert!(!piece_table.assert_text_eq("ello, World"));
+        assert!(!piece_table.assert_text_eq("ello, World?"));
+    }
+
+    #[test]
+    fn insert_line_after_the_first_line() {
+        let mut piece_table = PieceTable::from_string("one\nthree".to_owned());
+
+        piece_table.insert_line_after(0, "two").unwrap();
+
+        assert_eq!(&piece_table.to_string(), "one\ntwo\nthree");
+    }
+
+    #[test]
+    fn insert_line_after_the_last_line() {
+        let mut piece_table = PieceTable::from_string("one\ntwo".to_owned());
+
+        piece_table.insert_line_after(1, "three\n").unwrap();
+        assert_eq!(&piece_table.to_string(), "one\ntwo\nthree\n");
+
+        piece_table.insert_line_after(5, "four").unwrap();
+        assert_eq!(&piece_table.to_string(), "one\ntwo\nthree\nfour\n");
+
+        piece_table.insert_line_after(usize::MAX, "five").unwrap();
+        assert_eq!(&piece_table.to_string(), "one\ntwo\nthree\nfour\nfive\n");
+    }
+
+    #[test]
+    fn substring_collects_the_text_in_a_range() {
+        let mut piece_table = PieceTable::from_string("Hello World".to_owned());
+        piece_table.insert(5, ",").unwrap();
+        piece_table.insert(12, "!").unwrap();
+
+        assert_eq!(&piece_table.substring(3..9), "lo, Wo");
+        assert_eq!(&piece_table.substring(5..6), ",");
+        assert_eq!(&piece_table.substring(7..100), "World!");
+        assert_eq!(&piece_table.substring(50..100), "");
+    }
+
+    #[test]
+    fn compact_preserving_origin_keeps_change_regions() {
+        let mut piece_table = PieceTable::from_string("Hello World".to_owned());
+        piece_table.insert(5, ",").unwrap();
+        piece_table.insert(0, "Oh, ").unwrap();
+        piece_table.insert(16, "!").unwrap();
+        piece_table.delete(7, 2).unwrap();
+        let regions = piece_table.change_regions();
+
+        piece_table.compact_preserving_origin();
+
+        assert_eq!(&piece_table.to_string(), "Oh, Hel, World!");
+        assert_eq!(piece_table.change_regions(), regions);
+        assert_eq!(piece_table.buffers.len(), 2);
+        assert_eq!(piece_table.buffers[1].as_slice(), b"Oh, ,!");
+    }
+
+    #[test]
+    fn replace_swaps_text_in_a_single_edit() {
+        let mut piece_table = PieceTable::from_string("Hello World".to_owned());
+        piece_table.insert(5, ",").unwrap();
+        let version = piece_table.version();
+
+        piece_table.replace(3, 6, "p! Wo").unwrap();
+
+        assert_eq!(&piece_table.to_string(), "Help! World");
+        assert_eq!(piece_table.version(), version + 1);
+        assert!(piece_table.undo());
+        assert_eq!(&piece_table.to_string(), "Hello, World");
+    }
+
+    #[test]
+    fn replace_with_nothing_deletes_and_replacing_nothing_inserts() {
+        let mut piece_table = PieceTable::from_string("Hello World".to_owned());
+
+        piece_table.replace(5, 6, "").unwrap();
+        assert_eq!(&piece_table.to_string(), "Hello");
+
+        piece_table.replace(5, 0, ", World").unwrap();
+        assert_eq!(&piece_table.to_string(), "Hello, World");
+
+        assert_eq!(
+            piece_table.replace(50, 1, "!"),
+            Err(EditError::OutOfBounds(50))
+        );
+    }
+
+    #[test]
+    fn char_windows_span_piece_boundaries() {
+        let mut piece_table = PieceTable::from_string("abé".to_owned());
+        piece_table.insert(2, "X").unwrap();
+
+        let windows: Vec<String> = piece_table.char_windows(2).collect();
+
+        assert_eq!(windows, vec!["ab", "bX", "Xé"]);
+        assert_eq!(piece_table.char_windows(5).count(), 0);
+        assert_eq!(piece_table.char_windows(0).count(), 0);
+    }
+
+    #[test]
+    fn compact_reports_the_bytes_reclaimed() {
+        let mut piece_table = PieceTable::from_string("Hello World".to_owned());
+        piece_table.insert(5, ", cruel").unwrap();
+        piece_table.delete(5, 7).unwrap();
+        piece_table.delete(0, 1).unwrap();
+
+        assert_eq!(piece_table.compact(), 8);
+        assert_eq!(&piece_table.to_string(), "ello World");
+        assert_eq!(piece_table.buffers.len(), 1);
+        assert_eq!(piece_table.compact(), 0);
+    }
+
+    #[test]
+    fn first_difference_finds_an_early_difference() {
+        let mut piece_table = PieceTable::from_string("Hello World".to_owned());
+        piece_table.insert(5, ",").unwrap();
+
+        assert_eq!(piece_table.first_difference("Help, World"), Some(3));
+    }
+
+    #[test]
+    fn first_difference_finds_a_late_difference() {
+        let mut piece_table = PieceTable::from_string("Hello World".to_owned());
+        piece_table.insert(5, ",").unwrap();
+
+        assert_eq!(piece_table.first_difference("Hello, Worlds"), Some(12));
+        assert_eq!(piece_table.first_difference("Hello, Word"), Some(10));
+    }
+
+    #[test]
+    fn first_difference_of_equal_text_is_none() {
+        let mut piece_table = PieceTable::from_string("Hello World".to_owned());
+        piece_table.insert(5, ",").unwrap();
+
+        assert_eq!(piece_table.first_difference("Hello, World"), None);
+        assert_eq!(PieceTable::new().first_difference(""), None);
+    }
+
+    #[test]
+    fn locate_finds_pieces_after_edits() {
+        let mut piece_table = PieceTable::from_string("Hello World".to_owned());
+        for i in 0..10 {
+            piece_table.insert(i * 2, "-").unwrap();
+        }
+        assert_eq!(&piece_table.to_string(), "-H-e-l-l-o- -W-o-r-ld");
+
+        for position in 0..=piece_table.len() {
+            let loc = piece_table.locate(position);
+            let start: usize = piece_table.pieces[..loc.piece_index]
+                .iter()
+                .map(Piece::len)
+                .sum();
+            assert_eq!(start + loc.offset, position);
+            assert!(
+                loc.piece_index == piece_table.pieces.len()
+                    || loc.offset < piece_table.pieces[loc.piece_index].len()
+            );
+        }
+
+        piece_table.delete(1, 3).unwrap();
+        piece_table.compact();
+        assert_eq!(piece_table.locate(4).offset, 4);
+    }
+
+    #[test]
+    fn last_difference_finds_a_difference_at_the_end() {
+        let mut piece_table = PieceTable::from_string("Hello World".to_owned());
+        piece_table.insert(5, ",").unwrap();
+
+        assert_eq!(piece_table.last_difference("Hello, Worlds"), Some(12));
+        assert_eq!(piece_table.last_difference("Hello, Word"), Some(11));
+    }
+
+    #[test]
+    fn last_difference_finds_a_difference_in_the_middle() {
+        let mut piece_table = PieceTable::from_string("Hello World".to_owned());
+        piece_table.insert(5, ",").unwrap();
+
+        assert_eq!(piece_table.last_difference("Hello; World"), Some(6));
+        assert_eq!(piece_table.last_difference("Hello World"), Some(6));
+        assert_eq!(piece_table.last_difference("XHello, World"), Some(0));
+    }
+
+    #[test]
+    fn last_difference_of_equal_text_is_none() {
+        let mut piece_table = PieceTable::from_string("Hello World".to_owned());
+        piece_table.insert(5, ",").unwrap();
+
+        assert_eq!(piece_table.last_difference("Hello, World"), None);
+        assert_eq!(PieceTable::new().last_difference(""), None);
+    }
+
+    #[test]
+    fn clones_are_independent_of_the_original() {
+        let mut piece_table = PieceTable::from_string("Hello World".to_owned());
+        piece_table.insert(5, ",").unwrap();
+
+        let mut clone = piece_table.clone();
+        piece_table.insert(12, "!").unwrap();
+        clone.delete(0, 7).unwrap();
+
+        assert_eq!(&piece_table.to_string(), "Hello, World!");
+        assert_eq!(&clone.to_string(), "World");
+        assert!(clone.undo());
+        assert!(clone.undo());
+        assert_eq!(&clone.to_string(), "Hello World");
+    }
+
+    #[test]
+    fn merge_opportunities_flag_contiguous_neighbours() {
+        let mut piece_table = PieceTable::from_string("Hello World".to_owned());
+        piece_table.pieces = vec![
+            Piece {
+                buffer_index: 0,
+                start: 0,
+                end: 5,
+            },
+            Piece {
+                buffer_index: 0,
+                start: 5,
+                end: 11,
+            },
+            Piece {
+                buffer_index: 0,
+                start: 0,
+                end: 5,
+            },
+        ];
+
+        assert_eq!(piece_table.merge_opportunities(), vec![true, false]);
+        assert_eq!(PieceTable::new().merge_opportunities(), Vec::<bool>::new());
+    }
+
+    #[test]
+    fn snapshots_share_buffers_with_the_table() {
+        let mut piece_table = PieceTable::from_string("Hello World".to_owned());
+        piece_table.insert(5, ",").unwrap();
+
+        let snapshot = piece_table.snapshot();
+        piece_table.insert(6, " there").unwrap();
+
+        assert!(Arc::ptr_eq(&piece_table.buffers[0], &snapshot.buffers[0]));
+        assert!(Arc::ptr_eq(&piece_table.buffers[1], &snapshot.buffers[1]));
+        assert_eq!(piece_table.buffers.len(), 3);
+        assert_eq!(&snapshot.to_string(), "Hello, World");
+        assert_eq!(&piece_table.to_string(), "Hello, there World");
+    }
+
+    #[test]
+    fn split_off_shares_buffers_between_the_halves() {
+        let text = "Hello World\n".repeat(10_000);
+        let mut piece_table = PieceTable::from_string(text.clone());
+
+        let tail = piece_table.split_off(text.len() / 2);
+
+        assert!(Arc::ptr_eq(&piece_table.buffers[0], &tail.buffers[0]));
+        assert_eq!(Arc::strong_count(&tail.buffers[0]), 2);
+        assert_eq!(piece_table.to_string() + &tail.to_string(), text);
+    }
+
+    #[test]
+    fn expand_tabs_at_the_start_of_a_line() {
+        let mut piece_table = PieceTable::from_string("\tone\n\t\ttwo".to_owned());
+
+        piece_table.expand_tabs(4).unwrap();
+
+        assert_eq!(&piece_table.to_string(), "    one\n        two");
+        assert!(piece_table.undo());
+        assert_eq!(&piece_table.to_string(), "\tone\n\t\ttwo");
+        assert!(!piece_table.undo());
+    }
+
+    #[test]
+    fn expand_tabs_part_way_through_a_line() {
+        let mut piece_table = PieceTable::from_string("ab\tc\nabcde\tf".to_owned());
+        piece_table.insert(0, "é").unwrap();
+
+        piece_table.expand_tabs(8).unwrap();
+
+        assert_eq!(&piece_table.to_string(), "éab     c\nabcde   f");
+    }
+
+    #[test]
+    fn expand_tabs_with_a_width_of_four_mid_line() {
+        let mut piece_table = PieceTable::from_string("a\tbcd\te".to_owned());
+
+        piece_table.expand_tabs(4).unwrap();
+
+        assert_eq!(&piece_table.to_string(), "a   bcd e");
+    }
+
+    #[test]
+    fn from_reader_reads_the_whole_text() {
+        let piece_table = PieceTable::from_reader("Hello, Wörld".as_bytes()).unwrap();
+
+        assert_eq!(&piece_table.to_string(), "Hello, Wörld");
+        assert_eq!(piece_table.change_regions(), vec![]);
+    }
+
+    #[test]
+    fn from_reader_rejects_invalid_utf8() {
+        let error = PieceTable::from_reader(&b"Hello \xff"[..]).err().unwrap();
+
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn unexpand_tabs_converts_leading_spaces_to_tabs() {
+        let mut piece_table =
+            PieceTable::from_string("    one\n          two\n  \tthree".to_owned());
+
+        piece_table.unexpand_tabs(4).unwrap();
+
+        assert_eq!(&piece_table.to_string(), "\tone\n\t\t  two\n\tthree");
+        assert!(piece_table.undo());
+        assert!(!piece_table.undo());
+    }
+
+    #[test]
+    fn unexpand_tabs_leaves_spaces_after_the_indentation_alone() {
+        let mut piece_table = PieceTable::from_string("a       b\n  c    d".to_owned());
+
+        piece_table.unexpand_tabs(4).unwrap();
+
+        assert_eq!(&piece_table.to_string(), "a       b\n  c    d");
+        assert_eq!(piece_table.pieces.len(), 1);
+    }
+
+    #[test]
+    fn write_to_streams_every_piece() {
+        let mut piece_table = PieceTable::from_reader("Hello World".as_bytes()).unwrap();
+        piece_table.insert(5, ",").unwrap();
+        let mut output = Vec::new();
+
+        piece_table.write_to(&mut output).unwrap();
+
+        assert_eq!(output, b"Hello, World");
+    }
+
+    #[test]
+    fn advance_chars_steps_over_multibyte_characters() {
+        let mut piece_table = PieceTable::from_string("héllo".to_owned());
+        piece_table.insert(3, "→").unwrap();
+
+        assert_eq!(piece_table.advance_chars(0, 2), 3);
+        assert_eq!(piece_table.advance_chars(1, 2), 6);
+        assert_eq!(piece_table.advance_chars(3, 0), 3);
+        assert_eq!(piece_table.advance_chars(2, 1), 6);
+    }
+
+    #[test]
+    fn advance_chars_past_the_end_clamps_to_the_end() {
+        let mut piece_table = PieceTable::from_string("héllo".to_owned());
+        piece_table.insert(3, "→").unwrap();
+
+        assert_eq!(piece_table.advance_chars(6, 10), 9);
+        assert_eq!(piece_table.advance_chars(50, 1), 9);
+    }
+
+    #[test]
+    fn line_fetches_lines_across_pieces() {
+        let mut piece_table = PieceTable::from_string("one\nthree".to_owned());
+        piece_table.insert(4, "two\n").unwrap();
+
+        assert_eq!(piece_table.line_count(), 3);
+        assert_eq!(piece_table.line(0).as_deref(), Some("one"));
+        assert_eq!(piece_table.line(1).as_deref(), Some("two"));
+        assert_eq!(piece_table.line(2).as_deref(), Some("three"));
+        assert_eq!(piece_table.line(3), None);
+    }
+
+    #[test]
+    fn empty_documents_and_trailing_newlines_have_an_empty_last_line() {
+        let empty = PieceTable::new();
+        assert_eq!(empty.line_count(), 1);
+        assert_eq!(empty.line(0).as_deref(), Some(""));
+
+        let piece_table = PieceTable::from_string("one\n".to_owned());
+        assert_eq!(piece_table.line_count(), 2);
+        assert_eq!(piece_table.line(1).as_deref(), Some(""));
+        assert_eq!(piece_table.line(2), None);
+    }
+
+    #[test]
+    fn reinserting_deleted_text_rejoins_the_piece() {
+        let mut piece_table = PieceTable::from_string("Hello World".to_owned());
+        piece_table.delete(2, 3).unwrap();
+        assert_eq!(piece_table.pieces.len(), 2);
+
+        piece_table.insert(2, "llo").unwrap();
+
+        assert_eq!(&piece_table.to_string(), "Hello World");
+        assert_eq!(piece_table.pieces.len(), 1);
+        assert_eq!(piece_table.buffers.len(), 1);
+    }
+
+    #[test]
+    fn positions_convert_to_and_from_line_and_column() {
+        let mut piece_table = PieceTable::from_string("one\nthree".to_owned());
+        piece_table.insert(4, "two\n").unwrap();
+
+        assert_eq!(piece_table.position_to_line_col(0), (0, 0));
+        assert_eq!(piece_table.position_to_line_col(3), (0, 3));
+        assert_eq!(piece_table.position_to_line_col(4), (1, 0));
+        assert_eq!(piece_table.position_to_line_col(10), (2, 2));
+        assert_eq!(piece_table.line_col_to_position(1, 2), 6);
+        assert_eq!(piece_table.line_col_to_position(2, 2), 10);
+    }
+
+    #[test]
+    fn line_and_column_conversions_clamp_to_the_document() {
+        let piece_table = PieceTable::from_string("one\ntwo".to_owned());
+
+        assert_eq!(piece_table.position_to_line_col(100), (1, 3));
+        assert_eq!(piece_table.line_col_to_position(0, 100), 3);
+        assert_eq!(piece_table.line_col_to_position(100, 1), 5);
+        assert_eq!(PieceTable::new().line_col_to_position(3, 3), 0);
+    }
+
+    #[test]
+    fn debug_layout_lists_pieces_and_buffers() {
+        let mut piece_table = PieceTable::from_string("Hello World".to_owned());
+        piece_table.insert(5, ",").unwrap();
+
+        assert_eq!(
+            piece_table.debug_layout(),
+            concat!(
+                "pieces:\n",
+                "[0: \"Hello\"]\n",
+                "[1: \",\"]\n",
+                "[0: \" World\"]\n",
+                "buffers:\n",
+                "0: \"Hello World\"\n",
+                "1: \",\"\n",
+            )
+        );
+    }
+
+    #[test]
+    fn find_matches_across_piece_boundaries() {
+        let mut piece_table = PieceTable::from_string("abcab".to_owned());
+        piece_table.insert(3, "X").unwrap();
+        piece_table.insert(6, "cab").unwrap();
+
+        assert_eq!(&piece_table.to_string(), "abcXabcab");
+        assert_eq!(piece_table.find("Xab"), Some(3));
+        assert_eq!(piece_table.find("bca"), Some(5));
+        assert_eq!(piece_table.find("abd"), None);
+        assert_eq!(piece_table.find(""), None);
+
+        let mut matches = Vec::new();
+        let mut start = 0;
+        while let Some(position) = piece_table.find_from(start, "ab") {
+            matches.push(position);
+            start = position + 1;
+        }
+        assert_eq!(matches, vec![0, 4, 7]);
+    }
+
+    #[test]
+    fn len_stays_in_step_with_the_pieces_across_edits() {
+        let mut piece_table = PieceTable::from_string("Hello World".to_owned());
+        let other = PieceTable::from_string("brave ".to_owned());
+        for i in 0..20 {
+            piece_table.insert(i, "ab").unwrap();
+            piece_table.delete(i * 2 % piece_table.len(), 1).unwrap();
+            piece_table.replace(i, 2, "xyz").unwrap();
+            piece_table.splice_table(0..1, &other).unwrap();
+            let sum: usize = piece_table.pieces.iter().map(Piece::len).sum();
+            assert_eq!(piece_table.len(), sum);
+        }
+        piece_table.undo();
+        let sum: usize = piece_table.pieces.iter().map(Piece::len).sum();
+        assert_eq!(piece_table.len(), sum);
+        assert_eq!(piece_table.len(), piece_table.to_string().len());
+    }
+
+    #[test]
+    fn replace_all_replaces_every_occurrence_across_pieces() {
+        let mut piece_table = PieceTable::from_string("one cat, two cats".to_owned());
+        piece_table.insert(5, "-").unwrap();
+
+        assert_eq!(piece_table.replace_all("c-at", "dog"), 1);
+        assert_eq!(piece_table.replace_all("cat", "dog"), 1);
+        assert_eq!(&piece_table.to_string(), "one dog, two dogs");
+    }
+
+    #[test]
+    fn replace_all_doesnt_rescan_the_replacement() {
+        let mut piece_table = PieceTable::from_string("a-b-a".to_owned());
+
+        assert_eq!(piece_table.replace_all("a", "aa"), 2);
+        assert_eq!(&piece_table.to_string(), "aa-b-aa");
+        assert_eq!(piece_table.replace_all("x", "y"), 0);
+    }
+
+    #[test]
+    fn peek_delete_previews_a_deletion_across_pieces() {
+        let mut piece_table = PieceTable::from_string("Hello World".to_owned());
+        piece_table.insert(5, ",").unwrap();
+
+        assert_eq!(piece_table.peek_delete(3, 5).unwrap(), "lo, W");
+        assert_eq!(&piece_table.to_string(), "Hello, World");
+    }
+
+    #[test]
+    fn peek_delete_clamps_like_delete() {
+        let piece_table = PieceTable::from_string("Hello World".to_owned());
+
+        assert_eq!(piece_table.peek_delete(6, 100).unwrap(), "World");
+        assert_eq!(
+            piece_table.peek_delete(50, 1),
+            Err(EditError::OutOfBounds(50))
+        );
+
+        let piece_table = PieceTable::from_string("h\u{e9}llo".to_owned());
+        assert_eq!(
+            piece_table.peek_delete(0, 2),
+            Err(EditError::NotCharBoundary(2))
+        );
+    }
+
+    #[test]
+    fn anchors_move_with_edits_before_them() {
+        let mut piece_table = PieceTable::from_string("Hello World".to_owned());
+        let world = piece_table.add_anchor(6);
+        let end = piece_table.add_anchor(11);
+
+        piece_table.insert(5, ",").unwrap();
+        assert_eq!(piece_table.anchor_position(world), Some(7));
+        piece_table.delete(0, 2).unwrap();
+        assert_eq!(piece_table.anchor_position(world), Some(5));
+        piece_table.insert(10, "!").unwrap();
+        assert_eq!(piece_table.anchor_position(end), Some(10));
+        assert_eq!(&piece_table.substring(5..10), "World");
+
+        assert!(piece_table.remove_anchor(world));
+        assert_eq!(piece_table.anchor_position(world), None);
+        assert!(!piece_table.remove_anchor(world));
+    }
+
+    #[test]
+    fn anchor_bias_decides_the_side_of_text_inserted_at_the_anchor() {
+        let mut piece_table = PieceTable::from_string("ac".to_owned());
+        let left = piece_table.add_anchor_with_bias(1, Bias::Left);
+        let right = piece_table.add_anchor_with_bias(1, Bias::Right);
+
+        piece_table.insert(1, "b").unwrap();
+
+        assert_eq!(piece_table.anchor_position(left), Some(1));
+        assert_eq!(piece_table.anchor_position(right), Some(2));
+    }
+
+    #[test]
+    fn deleting_around_an_anchor_collapses_it() {
+        let mut piece_table = PieceTable::from_string("Hello cruel World".to_owned());
+        let inside = piece_table.add_anchor(8);
+        let after = piece_table.add_anchor(12);
+
+        piece_table.delete(6, 6).unwrap();
+
+        assert_eq!(piece_table.anchor_position(inside), Some(6));
+        assert_eq!(piece_table.anchor_position(after), Some(6));
+        assert_eq!(&piece_table.to_string(), "Hello World");
+    }
+
+    #[test]
+    fn insert_batch_returns_final_offsets() {
+        let mut table = PieceTable::from_string("abcdef".to_string());
+        let offsets = table.insert_batch(&[
+            (4, "XY".to_string()),
+            (0, "123".to_string()),
+            (4, "Z".to_string()),
+        ]);
+        assert_eq!(table.to_string(), "123abcdXYZef");
+        assert_eq!(offsets, vec![7, 0, 9]);
+        assert_eq!(&table.to_string()[7..9], "XY");
+        assert_eq!(&table.to_string()[9..10], "Z");
+    }
+
+    #[test]
+    fn fragmented_and_flat_tables_with_the_same_text_are_equal() {
+        let mut piece_table = PieceTable::from_string("ace".to_owned());
+        piece_table.insert(1, "b").unwrap();
+        piece_table.insert(3, "d").unwrap();
+        piece_table.insert(5, "f").unwrap();
+        let flat = PieceTable::from_string("abcdef".to_owned());
+
+        fn is_eq<T: Eq>(a: &T, b: &T) -> bool {
+            a == b
+        }
+        assert!(piece_table.pieces.len() > flat.pieces.len());
+        assert!(is_eq(&piece_table, &flat));
+    }
+
+    #[test]
+    fn equal_tables_hash_the_same_whatever_their_layout() {
+        fn hash_of(table: &PieceTable) -> u64 {
+            let mut hasher = DefaultHasher::new();
+            table.hash(&mut hasher);
+            hasher.finish()
+        }
+        let mut piece_table = PieceTable::from_string("Hello World".to_owned());
+        piece_table.insert(5, ",").unwrap();
+        let same = PieceTable::from_string("Hello, World".to_owned());
+        let different = PieceTable::from_string("Hello; World".to_owned());
+
+        assert_eq!(hash_of(&piece_table), hash_of(&same));
+        assert_ne!(hash_of(&piece_table), hash_of(&different));
+    }
+
+    #[test]
+    fn typing_one_char_at_a_time_makes_a_single_piece() {
+        let mut piece_table = PieceTable::new();
+        for (position, c) in "hello".chars().enumerate() {
+            piece_table.insert_char(position, c).unwrap();
+        }
+        assert_eq!(piece_table.to_string(), "hello");
+        assert_eq!(piece_table.pieces.len(), 1);
+
+        piece_table.insert_char(5, '\u{e9}').unwrap();
+        assert_eq!(piece_table.to_string(), "hello\u{e9}");
+        assert_eq!(piece_table.pieces.len(), 1);
+    }
+
+    #[test]
+    fn validate_names_the_first_bad_piece() {
+        let mut piece_table = PieceTable::from_string("h\u{e9}llo".to_owned());
+        piece_table.insert(6, " world").unwrap();
+        piece_table.delete(0, 1).unwrap();
+        assert_eq!(piece_table.validate(), Ok(()));
+
+        let mut bad = piece_table.clone();
+        bad.pieces[1].buffer_index = 7;
+        assert!(bad.validate().unwrap_err().starts_with("piece 1 "));
+
+        let mut bad = piece_table.clone();
+        bad.pieces[0].end = bad.pieces[0].start;
+        assert!(bad.validate().unwrap_err().starts_with("piece 0 "));
+
+        let mut bad = piece_table.clone();
+        bad.pieces[1].end += 1;
+        assert!(bad.validate().unwrap_err().starts_with("piece 1 "));
+
+        let mut bad = piece_table;
+        bad.pieces[0].start = 2;
+        assert!(bad.validate().unwrap_err().starts_with("piece 0 splits"));
+    }
+
+    #[test]
+    fn to_bytes_and_piece_bytes_follow_the_pieces() {
+        let mut piece_table = PieceTable::from_string("Hello World".to_owned());
+        piece_table.insert(5, ",").unwrap();
+        assert_eq!(piece_table.to_bytes(), b"Hello, World".to_vec());
+        assert_eq!(piece_table.piece_bytes(0), Some(&b"Hello"[..]));
+        assert_eq!(piece_table.piece_bytes(1), Some(&b","[..]));
+        assert_eq!(piece_table.piece_bytes(2), Some(&b" World"[..]));
+        assert_eq!(piece_table.piece_bytes(3), None);
+    }
+
+    #[test]
+    fn clear_empties_the_document_and_can_be_undone() {
+        let mut piece_table = PieceTable::from_string("Hello World".to_owned());
+        piece_table.insert(5, ",").unwrap();
+        piece_table.add_readonly_region(0..5);
+        let anchor = piece_table.add_anchor(7);
+
+        assert_eq!(piece_table.clear(), Err(EditError::ReadOnly(0)));
+        assert_eq!(piece_table.to_string(), "Hello, World");
+        piece_table.readonly_regions.clear();
+
+        piece_table.clear().unwrap();
+        assert_eq!(piece_table.to_string(), "");
+        assert_eq!(piece_table.len(), 0);
+        assert_eq!(piece_table.anchor_position(anchor), Some(0));
+        piece_table.insert(0, "new").unwrap();
+        assert_eq!(piece_table.to_string(), "new");
+
+        assert!(piece_table.undo());
+        assert!(piece_table.undo());
+        assert_eq!(piece_table.to_string(), "Hello, World");
+    }
+
+    #[test]
+    fn truncate_removes_the_tail() {
+        let mut piece_table = PieceTable::from_string("Hello World".to_owned());
+        piece_table.insert(5, ",").unwrap();
+        piece_table.truncate(20).unwrap();
+        assert_eq!(piece_table.to_string(), "Hello, World");
+        piece_table.truncate(3).unwrap();
+        assert_eq!(piece_table.to_string(), "Hel");
+        assert_eq!(piece_table.pieces.len(), 1);
+        piece_table.truncate(0).unwrap();
+        assert!(piece_table.is_empty());
+    }
+
+    #[test]
+    fn tables_can_be_parsed_and_defaulted() {
+        let piece_table: PieceTable = "Hello World".parse().unwrap();
+        assert_eq!(piece_table.to_string(), "Hello World");
+        assert_eq!(piece_table.pieces.len(), 1);
+
+        let empty: PieceTable = "".parse().unwrap();
+        assert!(empty == PieceTable::default());
+    }
+
+    #[test]
+    fn appending_a_split_off_table_rejoins_the_pieces() {
+        let mut piece_table = PieceTable::from_string("Hello World".to_owned());
+        let tail = piece_table.split_off_char(5);
+
+        piece_table.append_table(tail);
+
+        assert_eq!(&piece_table.to_string(), "Hello World");
+        assert_eq!(piece_table.buffers.len(), 1);
+        assert_eq!(piece_table.pieces.len(), 1);
     }
 
     #[test]
-    fn it_should_append_a_string_to_an_empty_piece_buffer() {
-        let mut piece_table = PieceTable::new();
+    fn split_off_at_the_start_moves_everything() {
+        let mut piece_table = PieceTable::from_string("Hello World".to_owned());
+        piece_table.insert(5, ",").unwrap();
 
-        piece_table.insert(0, "Hello, World");
+        let tail = piece_table.split_off(0);
 
-        assert_eq!(&piece_table.to_string(), "Hello, World");
+        assert!(piece_table.is_empty());
+        assert_eq!(piece_table.pieces.len(), 0);
+        assert_eq!(&tail.to_string(), "Hello, World");
+        assert_eq!(tail.len(), 12);
     }
 
     #[test]
-    fn inserting_at_beginning_should_prepend_text() {
-        let mut piece_table = PieceTable::new();
+    fn split_off_inside_a_character_splits_after_it() {
+        let mut piece_table = PieceTable::from_string("caf\u{e9}s".to_owned());
 
-        piece_table.insert(0, "World");
-        piece_table.insert(0, "Hello, ");
+        let tail = piece_table.split_off(4);
 
-        assert_eq!(&piece_table.to_string(), "Hello, World");
+        assert_eq!(&piece_table.to_string(), "caf\u{e9}");
+        assert_eq!(&tail.to_string(), "s");
+        assert_eq!(piece_table.validate(), Ok(()));
+        assert_eq!(tail.validate(), Ok(()));
     }
 
     #[test]
-    fn inserting_at_end_should_append_text() {
-        let mut piece_table = PieceTable::new();
+    fn fragmentation_statistics_track_edits_and_compaction() {
+        let mut piece_table = PieceTable::from_string("Hello World".to_owned());
+        piece_table.insert(5, ",").unwrap();
+        piece_table.delete(0, 1).unwrap();
+        assert_eq!(piece_table.piece_count(), 3);
+        assert_eq!(piece_table.buffer_count(), 2);
+        assert_eq!(piece_table.buffer_bytes(), 12);
+
+        piece_table.compact();
+        assert_eq!(piece_table.piece_count(), 1);
+        assert_eq!(piece_table.buffer_count(), 1);
+        assert_eq!(piece_table.buffer_bytes(), piece_table.len());
+        assert!(piece_table.buffer_capacity() >= piece_table.buffer_bytes());
+
+        let reserved = PieceTable::with_capacity(1000);
+        assert_eq!(reserved.buffer_bytes(), 0);
+        assert!(reserved.buffer_capacity() >= 1000);
+    }
 
-        piece_table.insert(0, "Hello, ");
-        piece_table.insert(7, "World");
+    #[test]
+    fn overwrite_replaces_text_across_pieces() {
+        let mut piece_table = PieceTable::from_string("Hello World".to_owned());
+        piece_table.insert(5, ",").unwrap();
 
+        piece_table.overwrite(3, "p me").unwrap();
+
+        assert_eq!(&piece_table.to_string(), "Help meWorld");
+        assert_eq!(piece_table.len(), 12);
+        assert!(piece_table.undo());
         assert_eq!(&piece_table.to_string(), "Hello, World");
     }
 
     #[test]
-    fn inserting_in_middle_should_split_original_text() {
-        let mut piece_table = PieceTable::new();
+    fn overwrite_past_the_end_extends_the_document() {
+        let mut piece_table = PieceTable::from_string("Hello".to_owned());
 
-        piece_table.insert(0, "Goodbye World");
-        piece_table.insert(7, " cruel");
+        piece_table.overwrite(3, "p me").unwrap();
+        assert_eq!(&piece_table.to_string(), "Help me");
 
-        assert_eq!(&piece_table.to_string(), "Goodbye cruel World");
+        piece_table.overwrite(7, "!").unwrap();
+        assert_eq!(&piece_table.to_string(), "Help me!");
+        assert_eq!(
+            piece_table.overwrite(9, "?"),
+            Err(EditError::OutOfBounds(9))
+        );
     }
 
     #[test]
-    fn delete_from_middle_removes_text() {
-        let mut piece_table = PieceTable::from_string("Hello, World".to_owned());
+    fn iterating_over_a_table_reference_yields_its_chunks() {
+        let mut piece_table = PieceTable::from_string("Hello World".to_owned());
+        piece_table.insert(5, ",").unwrap();
+
+        let mut chunks = Vec::new();
+        for chunk in &piece_table {
+            chunks.push(chunk);
+        }
+        assert_eq!(chunks, vec!["Hello", ",", " World"]);
+        assert_eq!(
+            (&piece_table).into_iter().collect::<String>(),
+            "Hello, World"
+        );
+        assert_eq!(piece_table.chunks().len(), 3);
+        assert_eq!(piece_table.chunks().next_back(), Some(" World"));
+    }
+
+    #[test]
+    fn extending_a_table_appends_to_a_single_piece() {
+        let mut piece_table = PieceTable::from_string("Log:".to_owned());
+        piece_table.add_readonly_region(0..4);
+
+        piece_table.extend(vec![" one", " two"]);
+        piece_table.extend(" three".chars());
+
+        assert_eq!(&piece_table.to_string(), "Log: one two three");
+        assert_eq!(piece_table.pieces.len(), 2);
+    }
+
+    #[test]
+    fn cursor_steps_through_characters_across_pieces() {
+        let mut piece_table = PieceTable::from_string("caf World".to_owned());
+        piece_table.insert(3, "\u{e9},").unwrap();
+        assert_eq!(&piece_table.to_string(), "caf\u{e9}, World");
+
+        let mut cursor = piece_table.cursor_at(2);
+        assert_eq!(cursor.next_char(), Some('f'));
+        assert_eq!(cursor.next_char(), Some('\u{e9}'));
+        assert_eq!(cursor.position(), 5);
+        assert_eq!(cursor.next_char(), Some(','));
+        assert_eq!(cursor.prev_char(), Some(','));
+        assert_eq!(cursor.prev_char(), Some('\u{e9}'));
+        assert_eq!(cursor.prev_char(), Some('f'));
+        assert_eq!(cursor.position(), 2);
+
+        assert!(cursor.seek(4));
+        assert_eq!(cursor.position(), 7);
+        assert_eq!(cursor.next_char(), Some('W'));
+        assert!(!cursor.seek(10));
+        assert_eq!(cursor.position(), piece_table.len());
+        assert_eq!(cursor.next_char(), None);
+        assert!(!cursor.seek(-20));
+        assert_eq!(cursor.position(), 0);
+        assert_eq!(cursor.prev_char(), None);
+
+        assert_eq!(piece_table.cursor_at(4).position(), 5);
+    }
+
+    #[test]
+    fn delete_range_accepts_any_kind_of_range() {
+        let mut piece_table = PieceTable::from_string("Hello World".to_owned());
+        piece_table.insert(5, ",").unwrap();
+
+        piece_table.delete_range(5..7).unwrap();
+        assert_eq!(&piece_table.to_string(), "HelloWorld");
+        piece_table.delete_range(8..=8).unwrap();
+        assert_eq!(&piece_table.to_string(), "HelloWord");
+        piece_table.delete_range(7..).unwrap();
+        assert_eq!(&piece_table.to_string(), "HelloWo");
+        piece_table.delete_range(..1).unwrap();
+        assert_eq!(&piece_table.to_string(), "elloWo");
+        piece_table.delete_range(..).unwrap();
+        assert!(piece_table.is_empty());
+    }
+
+    #[test]
+    fn insert_returns_the_range_it_occupies() {
+        let mut piece_table = PieceTable::from_string("Hello World".to_owned());
+
+        assert_eq!(piece_table.insert(5, ","), Ok(5..6));
+        assert_eq!(piece_table.insert_clamped(100, "!"), Ok(12..13));
+        assert_eq!(piece_table.insert(0, ""), Ok(0..0));
+        assert_eq!(piece_table.insert_char(0, '\u{e9}'), Ok(0..2));
+
+        piece_table.set_normalize_eol(true);
+        let range = piece_table.insert(2, "\r\n").unwrap();
+        assert_eq!(range, 2..3);
+        assert_eq!(piece_table.substring(range), "\n");
+    }
+
+    #[test]
+    fn coalesce_fuses_contiguous_pieces() {
+        let mut piece_table = PieceTable::from_string("Hello World".to_owned());
+        piece_table.split(piece_table.locate(3), 0);
+        piece_table.split(piece_table.locate(5), 0);
+        piece_table.insert(11, "!").unwrap();
+        assert_eq!(piece_table.pieces.len(), 4);
+        assert_eq!(piece_table.merge_opportunities(), vec![true, true, false]);
+
+        piece_table.coalesce();
+
+        assert_eq!(piece_table.pieces.len(), 2);
+        assert_eq!(&piece_table.to_string(), "Hello World!");
+        assert_eq!(piece_table.locate(11).piece_index, 1);
+    }
+
+    #[test]
+    fn fixed_buffer_growth_keeps_new_buffers_small() {
+        let mut piece_table = PieceTable::builder()
+            .original(&"x".repeat(1000))
+            .buffer_growth(BufferGrowth::Fixed(16))
+            .build();
+
+        piece_table.insert(0, "a").unwrap();
+        assert!((16..1000).contains(&piece_table.buffers[1].capacity()));
+        piece_table.insert(500, "b".repeat(40)).unwrap();
+        assert_eq!(piece_table.buffers.len(), 3);
+        assert!((40..1000).contains(&piece_table.buffers[2].capacity()));
+
+        let mut proportional = PieceTable::from_string("x".repeat(1000));
+        proportional.insert(0, "a").unwrap();
+        assert!(proportional.buffers[1].capacity() >= 1000);
+    }
+
+    #[test]
+    fn insert_fills_a_buffer_with_exactly_enough_room() {
+        let mut piece_table = PieceTable::from_string("Hello".to_owned());
+        piece_table.reserve_exact(6);
+        let spare = piece_table.buffers[1].capacity();
+
+        piece_table.insert(0, "x".repeat(spare)).unwrap();
 
-        piece_table.delete(5, 1);
+        assert_eq!(piece_table.buffers.len(), 2);
+        assert_eq!(
+            piece_table.buffers[1].len(),
+            piece_table.buffers[1].capacity()
+        );
+    }
+
+    #[test]
+    fn count_matches_finds_occurrences_spanning_pieces() {
+        let mut piece_table = PieceTable::from_string("banna ana".to_owned());
+        piece_table.insert(3, "a").unwrap();
+        assert_eq!(&piece_table.to_string(), "banana ana");
+
+        assert_eq!(piece_table.count_matches("ana"), 2);
+        assert_eq!(piece_table.count_matches("an"), 3);
+        assert_eq!(piece_table.count_matches("nan"), 1);
+        assert_eq!(piece_table.count_matches(""), 0);
+    }
+
+    #[test]
+    fn byte_at_and_char_at_read_single_positions() {
+        let mut piece_table = PieceTable::from_string("caf World".to_owned());
+        piece_table.insert(3, "\u{e9},").unwrap();
+
+        assert_eq!(piece_table.byte_at(0), Some(b'c'));
+        assert_eq!(piece_table.byte_at(4), Some(0xa9));
+        assert_eq!(piece_table.byte_at(5), Some(b','));
+        assert_eq!(piece_table.byte_at(11), Some(b'd'));
+        assert_eq!(piece_table.byte_at(12), None);
 
+        assert_eq!(piece_table.char_at(3), Some('\u{e9}'));
+        assert_eq!(piece_table.char_at(4), None);
+        assert_eq!(piece_table.char_at(6), Some(' '));
+        assert_eq!(piece_table.char_at(12), None);
+    }
+
+    #[test]
+    fn transaction_is_undone_in_one_step() {
+        let mut piece_table = PieceTable::from_string("Hello World".to_owned());
+        piece_table.insert(11, "!").unwrap();
+
+        let replaced = piece_table.transaction(|table| {
+            table.insert(5, ",").unwrap();
+            table.transaction(|table| table.replace_all("o", "0"))
+        });
+
+        assert_eq!(replaced, 2);
+        assert_eq!(&piece_table.to_string(), "Hell0, W0rld!");
+        assert!(piece_table.undo());
+        assert_eq!(&piece_table.to_string(), "Hello World!");
+        assert!(piece_table.redo());
+        assert_eq!(&piece_table.to_string(), "Hell0, W0rld!");
+        assert!(piece_table.undo());
+        assert!(piece_table.undo());
         assert_eq!(&piece_table.to_string(), "Hello World");
+        assert!(!piece_table.undo());
     }
 
     #[test]
-    fn delete_from_start_removes_text() {
-        let mut piece_table = PieceTable::from_string("Hello, World".to_owned());
+    fn transaction_rolls_back_when_it_panics() {
+        let mut piece_table = PieceTable::from_string("Hello World".to_owned());
+        let anchor = piece_table.add_anchor(6);
+        piece_table.insert(0, "> ").unwrap();
+        piece_table.undo();
+        let anchored = piece_table.anchor_position(anchor);
 
-        piece_table.delete(0, 7);
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            piece_table.transaction(|table| {
+                table.delete(0, 6).unwrap();
+                table.insert(0, "Goodbye").unwrap();
+                panic!("abandoned");
+            })
+        }));
 
-        assert_eq!(&piece_table.to_string(), "World");
+        assert!(result.is_err());
+        assert_eq!(&piece_table.to_string(), "Hello World");
+        assert_eq!(piece_table.anchor_position(anchor), anchored);
+        assert!(piece_table.redo());
+        assert_eq!(&piece_table.to_string(), "> Hello World");
+        assert_eq!(piece_table.validate(), Ok(()));
     }
 
     #[test]
-    fn delete_from_end_removes_text_without_adding_new_pieces() {
-        let mut piece_table = PieceTable::from_string("Hello, World".to_owned());
+    fn transaction_is_reported_as_one_change() {
+        use std::sync::Mutex;
+
+        let changes = Arc::new(Mutex::new(Vec::new()));
+        let mut piece_table = PieceTable::from_string("Hello World".to_owned());
+        let seen = Arc::clone(&changes);
+        piece_table.on_change(move |_, change| seen.lock().unwrap().push(change.clone()));
+        let version = piece_table.version();
 
-        piece_table.delete(5, 7);
+        piece_table.transaction(|table| {
+            table.insert(5, ",").unwrap();
+            table.replace(7, 5, "there").unwrap();
+        });
+        piece_table.transaction(|_| ());
 
-        assert_eq!(&piece_table.to_string(), "Hello");
+        assert_eq!(&piece_table.to_string(), "Hello, there");
+        assert_eq!(piece_table.version(), version + 1);
+        assert_eq!(
+            *changes.lock().unwrap(),
+            vec![Change {
+                removed: 5..11,
+                inserted: 5..12,
+            }]
+        );
+    }
+
+    #[test]
+    fn transaction_defers_compaction_until_it_ends() {
+        let mut piece_table = PieceTable::from_string("abcdef".to_owned());
+        piece_table.set_max_pieces(Some(3));
+
+        piece_table.transaction(|table| {
+            for position in [5, 3, 1] {
+                table.insert(position, "-").unwrap();
+            }
+            assert!(table.pieces.len() > 3);
+        });
+
+        assert_eq!(&piece_table.to_string(), "a-bc-de-f");
         assert_eq!(piece_table.pieces.len(), 1);
     }
 
     #[test]
-    fn delete_whole_piece_removes_piece() {
-        let mut piece_table = PieceTable::from_string("Hello, World".to_owned());
+    fn applying_a_diff_in_order_reproduces_the_other_table() {
+        let cases = [
+            ("Hello World", "Hello, World!"),
+            ("the quick brown fox", "a quick red fox jumps"),
+            ("caf\u{e9} au lait", "cafe\u{301} au th\u{e9}"),
+            ("", "new"),
+            ("old", ""),
+            ("same", "same"),
+        ];
+        for (from, to) in cases {
+            let mut piece_table = PieceTable::from_string(from.to_owned());
+            piece_table.insert(0, "").unwrap();
+            let other = PieceTable::from_string(to.to_owned());
 
-        piece_table.delete(0, 12);
+            let edits = piece_table.diff(&other);
+            for edit in &edits {
+                piece_table.apply_edit(edit).unwrap();
+            }
+            assert_eq!(piece_table.to_string(), to, "diffing {:?}", from);
+        }
 
-        assert_eq!(&piece_table.to_string(), "");
-        assert_eq!(piece_table.pieces.len(), 0);
+        let piece_table = PieceTable::from_string("Hello World".to_owned());
+        let other = PieceTable::from_string("Hello, World!".to_owned());
+        assert_eq!(
+            piece_table.diff(&other),
+            vec![
+                Edit::Insert {
+                    pos: 11,
+                    text: "!".to_owned()
+                },
+                Edit::Insert {
+                    pos: 5,
+                    text: ",".to_owned()
+                },
+            ]
+        );
+        assert!(piece_table.diff(&piece_table.clone()).is_empty());
     }
 
     #[test]
-    fn deleting_multiple_pieces_removes_all_pieces() {
+    fn apply_edits_uses_positions_in_the_original_document() {
         let mut piece_table = PieceTable::from_string("Hello World".to_owned());
+        piece_table.insert(11, "!").unwrap();
 
-        piece_table.insert(5, ",");
-        assert_eq!(piece_table.pieces.len(), 3); //Quick sanity check - if we've not got 3 pieces then the test isn't valid!
+        piece_table
+            .apply_edits(&[
+                Edit::Insert {
+                    pos: 5,
+                    text: ",".to_owned(),
+                },
+                Edit::Insert {
+                    pos: 12,
+                    text: "?".to_owned(),
+                },
+                Edit::Delete { pos: 6, len: 5 },
+                Edit::Insert {
+                    pos: 6,
+                    text: "there".to_owned(),
+                },
+                Edit::Insert {
+                    pos: 12,
+                    text: "!".to_owned(),
+                },
+            ])
+            .unwrap();
 
-        piece_table.delete(2, 10);
+        assert_eq!(&piece_table.to_string(), "Hello, there!?!");
+        assert!(piece_table.undo());
+        assert_eq!(&piece_table.to_string(), "Hello World!");
+    }
 
-        assert_eq!(&piece_table.to_string(), "He");
-        assert_eq!(piece_table.pieces.len(), 1);
+    #[test]
+    fn apply_edits_rejects_bad_batches_without_changing_anything() {
+        let mut piece_table = PieceTable::from_string("Hello World".to_owned());
+        let version = piece_table.version();
+
+        let overlapping = [
+            Edit::Delete { pos: 0, len: 5 },
+            Edit::Insert {
+                pos: 3,
+                text: "x".to_owned(),
+            },
+        ];
+        assert_eq!(
+            piece_table.apply_edits(&overlapping),
+            Err(EditError::Overlapping(3))
+        );
+        let out_of_bounds = [
+            Edit::Delete { pos: 0, len: 1 },
+            Edit::Delete { pos: 20, len: 1 },
+        ];
+        assert_eq!(
+            piece_table.apply_edits(&out_of_bounds),
+            Err(EditError::OutOfBounds(20))
+        );
+        assert_eq!(&piece_table.to_string(), "Hello World");
+        assert_eq!(piece_table.version(), version);
     }
 
     #[test]
-    fn inserting_past_end_inserts_at_end() {
-        let mut piece_table = PieceTable::from_string("Hello, World".to_owned());
-        piece_table.insert(500, "Boom");
-        assert_eq!(&piece_table.to_string(), "Hello, WorldBoom");
+    fn apply_edits_accepts_a_diff() {
+        let mut piece_table = PieceTable::from_string("the quick brown fox".to_owned());
+        let other = PieceTable::from_string("a quick red fox jumps".to_owned());
+
+        let edits = piece_table.diff(&other);
+        piece_table.apply_edits(&edits).unwrap();
+
+        assert!(piece_table == other);
     }
 
     #[test]
-    fn deleting_when_start_is_past_end_of_buffer_does_nothing() {
-        let mut piece_table = PieceTable::from_string("Hello, World".to_owned());
-        piece_table.delete(500, 1);
+    fn builder_can_turn_on_line_ending_normalization() {
+        let mut piece_table = PieceTable::builder()
+            .original("one\r\n")
+            .normalize_eol(true)
+            .build();
+
+        let range = piece_table.insert(5, "two\r\nthree\r").unwrap();
+
+        assert_eq!(range, 5..15);
+        assert_eq!(&piece_table.to_string(), "one\r\ntwo\nthree\n");
+        assert!(!PieceTable::builder().build().normalize_eol);
+    }
+
+    #[test]
+    fn observers_are_told_about_every_change() {
+        use std::sync::Mutex;
+
+        let changes = Arc::new(Mutex::new(Vec::new()));
+        let mut piece_table = PieceTable::from_string("Hello World".to_owned());
+        let seen = Arc::clone(&changes);
+        piece_table.on_change(move |table, change| {
+            seen.lock()
+                .unwrap()
+                .push((change.clone(), table.to_string()));
+        });
+
+        piece_table.insert(5, ",").unwrap();
+        piece_table.delete(0, 0).unwrap();
+        piece_table.replace(7, 5, "there").unwrap();
+        piece_table.delete(0, 7).unwrap();
+        piece_table.undo();
+
+        let changes = changes.lock().unwrap();
+        let expected = [
+            (5..5, 5..6, "Hello, World"),
+            (7..12, 7..12, "Hello, there"),
+            (0..7, 0..0, "there"),
+            (0..5, 0..12, "Hello, there"),
+        ];
+        assert_eq!(changes.len(), expected.len());
+        for ((change, text), (removed, inserted, expected_text)) in changes.iter().zip(expected) {
+            assert_eq!(change.removed, removed);
+            assert_eq!(change.inserted, inserted);
+            assert_eq!(text, expected_text);
+        }
+        assert_eq!(piece_table.clone().observers.0.len(), 0);
+    }
+
+    #[test]
+    fn line_ranges_cover_lines_with_or_without_newlines() {
+        let mut piece_table = PieceTable::from_string("one\nthree\n".to_owned());
+        piece_table.insert(4, "two\n").unwrap();
+
+        assert_eq!(piece_table.line_range(1), Some(4..7));
+        assert_eq!(piece_table.line_range_with_newline(1), Some(4..8));
+        assert_eq!(piece_table.line_range(3), Some(14..14));
+        assert_eq!(piece_table.line_range_with_newline(3), Some(14..14));
+        assert_eq!(piece_table.line_range(4), None);
+
+        let range = piece_table.line_range_with_newline(1).unwrap();
+        piece_table.delete_range(range).unwrap();
+        assert_eq!(&piece_table.to_string(), "one\nthree\n");
+    }
+
+    #[test]
+    fn lines_borrow_lines_held_in_one_piece() {
+        let mut piece_table = PieceTable::from_string("one\ntwo\nfour".to_owned());
+        piece_table.insert(8, "thr").unwrap();
+        piece_table.insert(11, "ee\n").unwrap();
+        piece_table.insert(3, " and").unwrap();
+
+        let lines: Vec<_> = piece_table.lines().collect();
+        assert_eq!(lines, vec!["one and", "two", "three", "four"]);
+        assert!(matches!(lines[0], Cow::Owned(_)));
+        assert!(matches!(lines[1], Cow::Borrowed(_)));
+        assert!(matches!(lines[2], Cow::Borrowed(_)));
+        assert!(matches!(lines[3], Cow::Borrowed(_)));
+
+        assert_eq!(PieceTable::new().lines().collect::<Vec<_>>(), vec![""]);
+        let trailing = PieceTable::from_string("a\n".to_owned());
+        assert_eq!(trailing.lines().collect::<Vec<_>>(), vec!["a", ""]);
+    }
+
+    #[test]
+    fn rebase_position_follows_inserts_and_deletes() {
+        let insert = Edit::Insert {
+            pos: 5,
+            text: ", there".to_owned(),
+        };
+        assert_eq!(insert.rebase_position(4), 4);
+        assert_eq!(insert.rebase_position(5), 12);
+        assert_eq!(insert.rebase_position(9), 16);
+
+        let delete = Edit::Delete { pos: 5, len: 3 };
+        assert_eq!(delete.rebase_position(5), 5);
+        assert_eq!(delete.rebase_position(6), 5);
+        assert_eq!(delete.rebase_position(8), 5);
+        assert_eq!(delete.rebase_position(10), 7);
+
+        let mut piece_table = PieceTable::from_string("Hello World".to_owned());
+        let position = 6;
+        piece_table.apply_edit(&insert).unwrap();
+        assert_eq!(
+            piece_table.char_at(insert.rebase_position(position)),
+            Some('W')
+        );
+    }
+
+    #[test]
+    fn pieces_debug_describes_each_piece() {
+        let mut piece_table = PieceTable::from_string("Hello World".to_owned());
+        piece_table.insert(5, ",").unwrap();
+
+        assert_eq!(
+            piece_table.pieces_debug(),
+            vec![
+                PieceInfo {
+                    buffer_index: 0,
+                    buffer_range: 0..5,
+                    start: 0,
+                    text: "Hello",
+                },
+                PieceInfo {
+                    buffer_index: 1,
+                    buffer_range: 0..1,
+                    start: 5,
+                    text: ",",
+                },
+                PieceInfo {
+                    buffer_index: 0,
+                    buffer_range: 5..11,
+                    start: 6,
+                    text: " World",
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn trim_trailing_whitespace_keeps_line_endings() {
+        let mut piece_table = PieceTable::from_string("one  \ntwo\t \r\n\nthree ".to_owned());
+        piece_table.insert(3, " \t").unwrap();
+        let anchor = piece_table.add_anchor(piece_table.len() - 6);
+
+        piece_table.trim_trailing_whitespace().unwrap();
+
+        assert_eq!(&piece_table.to_string(), "one\ntwo\r\n\nthree");
+        assert_eq!(piece_table.anchor_position(anchor), Some(10));
+        assert!(piece_table.undo());
+        assert_eq!(&piece_table.to_string(), "one \t  \ntwo\t \r\n\nthree ");
+
+        let mut read_only = PieceTable::from_string("a \nb ".to_owned());
+        read_only.add_readonly_region(3..5);
+        assert_eq!(
+            read_only.trim_trailing_whitespace(),
+            Err(EditError::ReadOnly(4))
+        );
+        assert_eq!(&read_only.to_string(), "a \nb ");
+    }
+
+    #[test]
+    fn map_range_replaces_text_with_a_function_of_it() {
+        let mut piece_table = PieceTable::from_string("Hello World".to_owned());
+        piece_table.insert(5, ",").unwrap();
+
+        piece_table
+            .map_range(3..8, |text| text.to_uppercase())
+            .unwrap();
+        assert_eq!(&piece_table.to_string(), "HelLO, World");
+        piece_table
+            .map_range(7..100, |text| format!("[{}]", text))
+            .unwrap();
+        assert_eq!(&piece_table.to_string(), "HelLO, [World]");
+        assert!(piece_table.undo());
+        assert_eq!(&piece_table.to_string(), "HelLO, World");
+
+        assert_eq!(
+            piece_table.map_range(20..21, |_| unreachable!()),
+            Err(EditError::OutOfBounds(20))
+        );
+    }
+
+    #[test]
+    fn content_eq_str_and_starts_with_compare_across_pieces() {
+        let mut piece_table = PieceTable::from_string("Hello World".to_owned());
+        piece_table.insert(5, ",").unwrap();
+
+        assert!(piece_table.content_eq_str("Hello, World"));
+        assert!(!piece_table.content_eq_str("Hello, World!"));
+        assert!(!piece_table.content_eq_str("Hello; World"));
+        assert!(piece_table.starts_with("Hello, W"));
+        assert!(piece_table.starts_with(""));
+        assert!(!piece_table.starts_with("Hello W"));
+        assert!(!piece_table.starts_with("Hello, World!"));
+    }
+
+    #[test]
+    fn insert_pieces_from_shares_the_source_buffers() {
+        let mut piece_table = PieceTable::from_string("Hello World".to_owned());
+        piece_table.insert(5, ",").unwrap();
+        let copy = piece_table.snapshot();
+
+        piece_table.insert_pieces_from(0, &copy, 3..8).unwrap();
+
+        assert_eq!(&piece_table.to_string(), "lo, WHello, World");
+        assert_eq!(piece_table.buffers.len(), 2);
+        assert_eq!(piece_table.validate(), Ok(()));
+        assert!(piece_table.undo());
         assert_eq!(&piece_table.to_string(), "Hello, World");
+
+        let other = PieceTable::from_string("caf\u{e9}!".to_owned());
+        piece_table.insert_pieces_from(12, &other, 2..4).unwrap();
+        assert_eq!(&piece_table.to_string(), "Hello, Worldf\u{e9}");
+        assert_eq!(piece_table.buffers.len(), 3);
+        assert_eq!(
+            piece_table.insert_pieces_from(20, &other, 0..1),
+            Err(EditError::OutOfBounds(20))
+        );
     }
 
     #[test]
-    fn deleting_when_it_would_delete_past_the_end_deletes_to_end() {
-        let mut piece_table = PieceTable::from_string("Hello, World".to_owned());
-        piece_table.delete(5, 500);
+    fn with_capacity_streams_text_into_one_buffer() {
+        let mut piece_table = PieceTable::with_capacity(100);
+        let capacity = piece_table.buffers[0].capacity();
+        for _ in 0..25 {
+            let len = piece_table.len();
+            piece_table.insert(len, "abcd").unwrap();
+        }
+
+        assert_eq!(piece_table.len(), 100);
+        assert_eq!(piece_table.buffer_count(), 1);
+        assert_eq!(piece_table.piece_count(), 1);
+        assert_eq!(piece_table.buffers[0].capacity(), capacity);
+        piece_table.insert(0, "x").unwrap();
+        assert_eq!(piece_table.buffer_count(), 2);
+        assert!(piece_table.buffers[1].capacity() >= 100);
+    }
+
+    #[test]
+    fn enumerate_pieces_yields_the_index_and_offset_of_each_piece() {
+        let mut piece_table = PieceTable::from_string("Hello World".to_owned());
+        piece_table.insert(5, ",").unwrap();
+
+        let pieces: Vec<_> = piece_table.enumerate_pieces().collect();
+
+        assert_eq!(pieces, vec![(0, 0, "Hello"), (1, 5, ","), (2, 6, " World")]);
+        assert_eq!(PieceTable::new().enumerate_pieces().count(), 0);
+    }
+
+    #[test]
+    fn deleting_across_interior_pieces_removes_exactly_the_covered_bytes() {
+        let mut piece_table = PieceTable::from_string("AAAEEE".to_owned());
+        for s in ["DDD", "CCC", "BBB"] {
+            piece_table.insert(3, s).unwrap();
+        }
+        assert_eq!(piece_table.piece_count(), 5);
+        let mut partial = piece_table.snapshot();
+
+        piece_table.delete(3, 9).unwrap();
+        assert_eq!(&piece_table.to_string(), "AAAEEE");
+        assert_eq!(piece_table.piece_count(), 1);
+
+        partial.delete(4, 7).unwrap();
+        assert_eq!(&partial.to_string(), "AAABDEEE");
+        assert_eq!(partial.piece_count(), 4);
+        assert_eq!(partial.validate(), Ok(()));
+    }
+
+    #[test]
+    fn word_at_finds_the_word_across_pieces() {
+        let mut piece_table = PieceTable::from_string("let caf = x;".to_owned());
+        piece_table.insert(7, "\u{e9}_b").unwrap();
+        piece_table.insert(4, "my").unwrap();
+
+        let word = (4..13, "mycaf\u{e9}_b".to_owned());
+        assert_eq!(piece_table.word_at(4), Some(word.clone()));
+        assert_eq!(piece_table.word_at(9), Some(word.clone()));
+        assert_eq!(piece_table.word_at(12), Some(word));
+        assert_eq!(piece_table.word_at(0), Some((0..3, "let".to_owned())));
+        assert_eq!(piece_table.word_at(13), None);
+        assert_eq!(piece_table.word_at(17), None);
+        assert_eq!(piece_table.word_at(10), None);
+        assert_eq!(piece_table.word_at(18), None);
+    }
+
+    #[test]
+    fn empty_documents_have_no_pieces_and_stay_valid() {
+        let mut piece_table = PieceTable::from_string(String::new());
+        assert_eq!(piece_table.piece_count(), 0);
+        piece_table.insert(0, "Hello").unwrap();
+        assert_eq!(piece_table.validate(), Ok(()));
         assert_eq!(&piece_table.to_string(), "Hello");
+
+        let mut parsed: PieceTable = "".parse().unwrap();
+        parsed.insert(0, "Hello").unwrap();
+        assert_eq!(parsed.validate(), Ok(()));
+
+        let mut read = PieceTable::from_reader(io::empty()).unwrap();
+        read.insert(0, "Hello").unwrap();
+        assert_eq!(read.validate(), Ok(()));
+    }
+
+    #[test]
+    fn styled_chunks_widen_styles_to_character_boundaries() {
+        let piece_table = PieceTable::from_string("h\u{e9}llo w\u{f6}rld".to_owned());
+        let styles = [(0..2, "a"), (2..3, "b"), (5..5, "c"), (9..10, "d")];
+
+        let chunks: Vec<_> = piece_table.styled_chunks(&styles).collect();
+
+        assert_eq!(
+            chunks,
+            vec![
+                ("h\u{e9}", Some(&"a")),
+                ("llo w", None),
+                ("\u{f6}", Some(&"d")),
+                ("rld", None),
+            ]
+        );
+    }
+
+    #[test]
+    fn trim_matches_changes_nothing_if_either_end_is_read_only() {
+        let mut piece_table = PieceTable::from_string("  ab  ".to_owned());
+        piece_table.add_readonly_region(0..1);
+
+        assert_eq!(
+            piece_table.trim_matches(char::is_whitespace),
+            Err(EditError::ReadOnly(0))
+        );
+        assert_eq!(&piece_table.to_string(), "  ab  ");
+
+        let mut piece_table = PieceTable::from_string("  ab  ".to_owned());
+        let version = piece_table.version();
+        piece_table.trim_matches(char::is_whitespace).unwrap();
+        assert_eq!(&piece_table.to_string(), "ab");
+        assert_eq!(piece_table.version(), version + 1);
+        assert!(piece_table.undo());
+        assert_eq!(&piece_table.to_string(), "  ab  ");
+        assert!(!piece_table.undo());
+    }
+
+    #[test]
+    fn deleting_nothing_leaves_the_version_alone() {
+        let mut piece_table = PieceTable::from_string("Hello World".to_owned());
+        let version = piece_table.version();
+
+        piece_table.delete(3, 0).unwrap();
+        piece_table.delete(11, 5).unwrap();
+        piece_table.truncate_chars(20).unwrap();
+        assert!(piece_table.strip_prefix(""));
+
+        assert_eq!(piece_table.version(), version);
+        assert_eq!(
+            piece_table.apply_if_version(version, &Edit::Delete { pos: 0, len: 6 }),
+            Ok(version + 1)
+        );
+        assert!(piece_table.undo());
+        assert!(!piece_table.undo());
+    }
+
+    #[test]
+    fn piece_tables_can_be_shared_between_threads() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<PieceTable>();
+
+        let mut piece_table = PieceTable::from_string("Hello World".to_owned());
+        piece_table.insert(5, ",").unwrap();
+        let shared = &piece_table;
+        let len = std::thread::scope(|scope| scope.spawn(|| shared.len()).join().unwrap());
+        assert_eq!(len, 12);
+    }
+
+    #[test]
+    fn binary_tables_hold_any_bytes() {
+        let mut piece_table = PieceTable::from_bytes(vec![0x00, 0xff, 0x10]);
+        assert!(piece_table.is_binary());
+
+        piece_table.insert_bytes(1, &[0xc3, 0x80, 0xfe]).unwrap();
+        piece_table.delete_bytes(0, 1).unwrap();
+        assert_eq!(piece_table.to_bytes(), vec![0xc3, 0x80, 0xfe, 0xff, 0x10]);
+        assert_eq!(piece_table.piece_bytes(0), Some(&[0xc3, 0x80, 0xfe][..]));
+        assert_eq!(piece_table.byte_at(3), Some(0xff));
+        assert_eq!(piece_table.validate(), Ok(()));
+        assert_eq!(&piece_table.to_string(), "\u{c0}\u{fffd}\u{fffd}\u{10}");
+
+        assert!(piece_table.undo());
+        assert_eq!(
+            piece_table.to_bytes(),
+            vec![0x00, 0xc3, 0x80, 0xfe, 0xff, 0x10]
+        );
+
+        piece_table.compact_with_chunk_size(2);
+        assert_eq!(
+            piece_table.to_bytes(),
+            vec![0x00, 0xc3, 0x80, 0xfe, 0xff, 0x10]
+        );
+        assert_eq!(piece_table.buffer_count(), 3);
+    }
+
+    #[test]
+    fn byte_edits_keep_a_table_textual_while_its_text_stays_valid() {
+        let mut piece_table = PieceTable::from_bytes("caf\u{e9}".as_bytes().to_vec());
+        assert!(!piece_table.is_binary());
+
+        piece_table.insert_bytes(5, " au lait".as_bytes()).unwrap();
+        piece_table.delete_bytes(0, 1).unwrap();
+        assert!(!piece_table.is_binary());
+        assert_eq!(&piece_table.to_string(), "af\u{e9} au lait");
+
+        piece_table.add_readonly_region(5..7);
+        assert_eq!(
+            piece_table.insert_bytes(6, &[0xff]),
+            Err(EditError::ReadOnly(6))
+        );
+        assert_eq!(
+            piece_table.insert_bytes(20, b"!"),
+            Err(EditError::OutOfBounds(20))
+        );
+        assert!(!piece_table.is_binary());
+
+        piece_table.delete_bytes(2, 1).unwrap();
+        assert!(piece_table.is_binary());
+        assert_eq!(piece_table.to_bytes(), b"af\xa9 au lait");
+    }
+
+    #[test]
+    fn apply_hunks_accepts_an_insertion_at_a_removal_in_either_order() {
+        let removal = Hunk {
+            start: 2,
+            remove: 2,
+            insert: "X".to_owned(),
+        };
+        let insertion = Hunk {
+            start: 2,
+            remove: 0,
+            insert: "Y".to_owned(),
+        };
+        for hunks in [
+            [removal.clone(), insertion.clone()],
+            [insertion.clone(), removal.clone()],
+        ] {
+            let mut piece_table = PieceTable::from_string("abcdef".to_owned());
+            piece_table.apply_hunks(&hunks).unwrap();
+            assert_eq!(&piece_table.to_string(), "abYXef");
+        }
+    }
+
+    #[test]
+    fn undo_history_is_bounded_by_default() {
+        let mut piece_table = PieceTable::new();
+        for i in 0..DEFAULT_HISTORY_LIMIT + 10 {
+            piece_table.insert(i, "x").unwrap();
+        }
+        let mut undone = 0;
+        while piece_table.undo() {
+            undone += 1;
+        }
+        assert_eq!(undone, DEFAULT_HISTORY_LIMIT);
+        assert_eq!(piece_table.len(), 10);
+    }
+
+    #[test]
+    #[should_panic(expected = "a binary table has no text parts")]
+    fn binary_tables_cannot_be_taken_apart_into_text() {
+        PieceTable::from_bytes(vec![0xff, 0x00]).into_parts();
+    }
+
+    #[test]
+    fn appending_or_splitting_off_nothing_leaves_the_version_alone() {
+        use std::sync::Mutex;
+
+        let mut piece_table = PieceTable::from_string("Hello".to_string());
+        let changes = Arc::new(Mutex::new(0));
+        let counter = Arc::clone(&changes);
+        piece_table.on_change(move |_, _| *counter.lock().unwrap() += 1);
+
+        piece_table.append_table(PieceTable::new());
+        let tail = piece_table.split_off(piece_table.len());
+
+        assert!(tail.is_empty());
+        assert_eq!(piece_table.to_string(), "Hello");
+        assert_eq!(piece_table.version(), 0);
+        assert!(!piece_table.undo());
+        assert_eq!(*changes.lock().unwrap(), 0);
+    }
+
+    #[test]
+    fn insert_at_anchor_follows_the_anchor_and_moves_it_past_the_text() {
+        let mut piece_table = PieceTable::from_string("Hello world".to_string());
+        let anchor = piece_table.add_anchor(5);
+        piece_table.insert(0, ">> ").unwrap();
+        piece_table.delete(0, 1).unwrap();
+
+        assert_eq!(piece_table.insert_at_anchor(anchor, ",").unwrap(), 7..8);
+        assert_eq!(piece_table.insert_at_anchor(anchor, " my").unwrap(), 8..11);
+        assert_eq!(piece_table.to_string(), "> Hello, my world");
+        assert_eq!(piece_table.anchor_position(anchor), Some(11));
+
+        piece_table.remove_anchor(anchor);
+        assert_eq!(
+            piece_table.insert_at_anchor(anchor, "!"),
+            Err(EditError::NoSuchAnchor)
+        );
     }
 }